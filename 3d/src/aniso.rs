@@ -0,0 +1,120 @@
+extern crate nalgebra as na;
+use na::{Vec3, Norm};
+use state::State;
+use real::Real;
+
+// direction fields are axial: d and -d mean the same thing
+#[inline]
+fn aligned(base: &Vec3<Real>, other: &Vec3<Real>) -> Vec3<Real> {
+    if na::dot(base, other) < 0.0 {-*other} else {*other}
+}
+
+#[inline]
+fn unit_or(v: Vec3<Real>, fallback: Vec3<Real>) -> Vec3<Real> {
+    if v.norm() < 1e-9 {fallback} else {v.normalize()}
+}
+
+impl State {
+    /// Direction for a seed node at `angle` around the ring: `aniso_dir` if
+    /// it's set, otherwise the ring's tangent.
+    pub fn start_dir(&self, angle: Real) -> Vec3<Real> {
+        let (x, y, z) = self.params.aniso_dir;
+        unit_or(Vec3::new(x, y, z), Vec3::new(-angle.sin(), angle.cos(), 0.0))
+    }
+
+    pub fn blend_dir(&self, a: usize, b: usize) -> Vec3<Real> {
+        let da = self.pts[a].dir;
+        unit_or(da + aligned(&da, &self.pts[b].dir), da)
+    }
+
+    /// How well edge a-b lines up with its ends' directions, in [0, 1].
+    pub fn alignment(&self, a: usize, b: usize) -> Real {
+        let along = self.pts[b].pos - self.pts[a].pos;
+        if along.norm() < 1e-9 {
+            return 1.0;
+        }
+        na::dot(&along.normalize(), &self.blend_dir(a, b)).abs()
+    }
+
+    pub fn normals(&self) -> Vec<Vec3<Real>> {
+        let mut normals = vec![Vec3::new(0.0, 0.0, 0.0); self.pts.len()];
+        for tri in self.tris.iter() {
+            let (a, b, c) = (tri.x as usize, tri.y as usize, tri.z as usize);
+            let normal = na::cross(&(self.pts[b].pos - self.pts[a].pos), &(self.pts[c].pos - self.pts[a].pos));
+            for &i in [a, b, c].iter() {
+                normals[i] = normals[i] + aligned(&normals[i], &normal);
+            }
+        }
+        normals
+    }
+
+    /// Carry the directions along the surface: blend in the neighbours'
+    /// directions, twist about the normal, and project back into the
+    /// tangent plane.
+    pub fn transport(&mut self) {
+        let mut pulled: Vec<Vec3<Real>> = self.pts.iter().map(|n| n.dir).collect();
+        for edge in self.edges.iter() {
+            let (da, db) = (self.pts[edge.a].dir, self.pts[edge.b].dir);
+            pulled[edge.a] = pulled[edge.a] + aligned(&da, &db) * self.params.aniso_smooth;
+            pulled[edge.b] = pulled[edge.b] + aligned(&db, &da) * self.params.aniso_smooth;
+        }
+        let normals = self.normals();
+        let twist = self.params.aniso_twist;
+        for i in 0..self.pts.len() {
+            let old = self.pts[i].dir;
+            let mut dir = unit_or(pulled[i], old);
+            if normals[i].norm() > 1e-9 {
+                let n = normals[i].normalize();
+                dir = dir - n * na::dot(&dir, &n);
+                if twist != 0.0 {
+                    // rodrigues, with dir already perpendicular to n
+                    dir = dir * twist.cos() + na::cross(&n, &dir) * twist.sin();
+                }
+            }
+            self.pts[i].dir = unit_or(dir, old);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use na::{Pnt3, Vec3, Norm};
+    use real::Real;
+    use testing::{node, edge, anchored};
+
+    fn close(a: Vec3<Real>, b: Vec3<Real>) -> bool {
+        (a - b).norm() < 1e-5
+    }
+
+    #[test]
+    fn splits_take_the_blend_of_their_ends() {
+        let mut a = node(0.0, 0.0, 0.0);
+        let mut b = node(1.0, 0.0, 0.0);
+        a.dir = Vec3::new(1.0, 0.0, 0.0);
+        b.dir = Vec3::new(0.0, 1.0, 0.0);
+        let mut state = anchored(vec![a, b]);
+        let half = (0.5 as Real).sqrt();
+        assert!(close(state.blend_dir(10, 11), Vec3::new(half, half, 0.0)));
+        // d and -d are the same direction, so they don't cancel out
+        state.pts[11].dir = Vec3::new(-1.0, 0.0, 0.0);
+        assert!(close(state.blend_dir(10, 11), Vec3::new(1.0, 0.0, 0.0)));
+        assert_eq!(state.alignment(10, 11), 1.0);
+    }
+
+    #[test]
+    fn transport_keeps_directions_in_the_surface() {
+        let mut state = anchored(vec![node(0.0, 0.0, 0.0), node(1.0, 0.0, 0.0), node(0.0, 1.0, 0.0)]);
+        for i in 10..13 {
+            state.pts[i].dir = Vec3::new(1.0, 0.0, 1.0).normalize();
+        }
+        state.edges.push(edge(10, 11, 1.0));
+        state.edges.push(edge(11, 12, 1.0));
+        state.edges.push(edge(12, 10, 1.0));
+        state.tris.push(Pnt3::new(10, 11, 12));
+        state.params.aniso_twist = 0.0;
+        state.transport();
+        for i in 10..13 {
+            assert!(close(state.pts[i].dir, Vec3::new(1.0, 0.0, 0.0)), "{:?}", state.pts[i].dir);
+        }
+    }
+}
@@ -0,0 +1,209 @@
+extern crate nalgebra as na;
+use na::{Pnt3, Vec3};
+use rustc_serialize::json;
+use std::fs::File;
+use std::io::Read;
+use std::f32::consts::PI;
+
+/// Where the camera is and what it's looking at.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Shot {
+    pub eye: Pnt3<f32>,
+    pub at: Pnt3<f32>,
+    pub fov: f32,
+}
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone, Copy, Debug)]
+pub enum Ease {
+    Linear,
+    Smooth,
+}
+
+/// A keyframe. Leaving out `eye` and `at` (or setting `frame`) frames the
+/// growth's bounding box at that time instead; a missing `fov` carries on
+/// from the key before.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone, Debug)]
+pub struct Key {
+    pub time: i32,
+    pub eye: Option<(f32, f32, f32)>,
+    pub at: Option<(f32, f32, f32)>,
+    pub fov: Option<f32>,
+    pub frame: Option<bool>,
+}
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone, Copy, Debug)]
+pub enum MoveKind {
+    /// Swing the eye around the target about the y axis, `amount` radians
+    /// in all.
+    Orbit,
+    /// Scale the eye's distance from the target, ending at `amount` times.
+    Dolly,
+}
+
+/// A move layered over the keyframes, running from `start` to `end`.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone, Copy, Debug)]
+pub struct Move {
+    pub kind: MoveKind,
+    pub start: i32,
+    pub end: i32,
+    pub amount: f32,
+}
+
+/// Camera keyframes and moves over time. Time is whatever the caller counts
+/// in: ticks while growing, frames when showing a finished state.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone, Debug)]
+pub struct CameraScript {
+    pub ease: Ease,
+    pub keys: Vec<Key>,
+    pub moves: Vec<Move>,
+}
+
+/// An eye and target that take in the whole bounding box.
+pub fn framing(bounds: (Pnt3<f32>, Pnt3<f32>)) -> (Pnt3<f32>, Pnt3<f32>) {
+    let (min, max) = bounds;
+    let center = min + (max - min) / 2.0;
+    let size = na::norm(&(max - min)).max(1.0);
+    (center + Vec3::new(0.0, size * 0.3, -size * 1.2), center)
+}
+
+/// Looking at the middle of the bounding box from as far out as `framing`,
+/// `yaw` radians around the y axis from the front and `elevation` up.
+pub fn orbit(bounds: (Pnt3<f32>, Pnt3<f32>), yaw: f32, elevation: f32) -> Shot {
+    let (eye, at) = framing(bounds);
+    let dist = na::norm(&(eye - at));
+    let (sin_yaw, cos_yaw) = yaw.sin_cos();
+    let (sin_up, cos_up) = elevation.sin_cos();
+    let arm = Vec3::new(-sin_yaw * cos_up, sin_up, -cos_yaw * cos_up) * dist;
+    Shot{eye: at + arm, at: at, fov: PI / 4.0}
+}
+
+fn pnt(v: (f32, f32, f32)) -> Pnt3<f32> {
+    Pnt3::new(v.0, v.1, v.2)
+}
+
+fn progress(time: i32, start: i32, end: i32) -> f32 {
+    if end <= start {
+        return if time >= start {1.0} else {0.0};
+    }
+    ((time - start) as f32 / (end - start) as f32).max(0.0).min(1.0)
+}
+
+impl CameraScript {
+    pub fn load(path: String) -> CameraScript {
+        let mut text = String::new();
+        File::open(path.clone()).ok().expect(&format!("Can't read camera script: {}", path))
+            .read_to_string(&mut text).ok().expect(&format!("Can't read camera script: {}", path));
+        let mut script: CameraScript = json::decode(&text).ok().expect(&format!("Unable to load camera script - is the format right? {}", path));
+        script.keys.sort_by(|a, b| a.time.cmp(&b.time));
+        script
+    }
+
+    // a key made concrete, framing `bounds` where it asks to
+    fn resolve(&self, k: usize, bounds: Option<(Pnt3<f32>, Pnt3<f32>)>) -> Shot {
+        let key = &self.keys[k];
+        let fov = (0..k + 1).rev().filter_map(|i| self.keys[i].fov).next().unwrap_or(PI / 4.0);
+        let framed = key.frame.unwrap_or(false) || key.eye.is_none() || key.at.is_none();
+        let (eye, at) = match (framed, bounds) {
+            (true, Some(bounds)) => framing(bounds),
+            _ => (
+                key.eye.map(pnt).unwrap_or(Pnt3::new(0.0, 0.0, -50.0)),
+                key.at.map(pnt).unwrap_or(na::orig()),
+            ),
+        };
+        Shot{eye: eye, at: at, fov: fov}
+    }
+
+    /// The shot at `time`. `bounds` is the growth's bounding box then, for
+    /// keys that frame it.
+    pub fn shot(&self, time: i32, bounds: Option<(Pnt3<f32>, Pnt3<f32>)>) -> Shot {
+        let mut shot = if self.keys.is_empty() {
+            let (eye, at) = match bounds {
+                Some(bounds) => framing(bounds),
+                None => (Pnt3::new(0.0, 0.0, -50.0), na::orig()),
+            };
+            Shot{eye: eye, at: at, fov: PI / 4.0}
+        } else {
+            let next = self.keys.iter().position(|key| key.time > time);
+            match next {
+                Some(0) => self.resolve(0, bounds),
+                None => self.resolve(self.keys.len() - 1, bounds),
+                Some(k) => {
+                    let (a, b) = (self.resolve(k - 1, bounds), self.resolve(k, bounds));
+                    let mut t = progress(time, self.keys[k - 1].time, self.keys[k].time);
+                    if self.ease == Ease::Smooth {
+                        t = t * t * (3.0 - 2.0 * t);
+                    }
+                    Shot {
+                        eye: a.eye + (b.eye - a.eye) * t,
+                        at: a.at + (b.at - a.at) * t,
+                        fov: a.fov + (b.fov - a.fov) * t,
+                    }
+                },
+            }
+        };
+
+        for step in self.moves.iter() {
+            let t = progress(time, step.start, step.end);
+            let arm = shot.eye - shot.at;
+            let arm = match step.kind {
+                MoveKind::Orbit => {
+                    let (sin, cos) = (step.amount * t).sin_cos();
+                    Vec3::new(arm.x * cos - arm.z * sin, arm.y, arm.x * sin + arm.z * cos)
+                },
+                MoveKind::Dolly => arm * (1.0 + (step.amount - 1.0) * t),
+            };
+            shot.eye = shot.at + arm;
+        }
+        shot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CameraScript, Ease, Key, Move, MoveKind, framing};
+    use na::{self, Pnt3};
+    use std::f32::consts::PI;
+
+    fn key(time: i32, eye: (f32, f32, f32), fov: Option<f32>) -> Key {
+        Key{time: time, eye: Some(eye), at: Some((0.0, 0.0, 0.0)), fov: fov, frame: None}
+    }
+
+    fn close(a: Pnt3<f32>, b: Pnt3<f32>) -> bool {
+        na::norm(&(a - b)) < 1e-4
+    }
+
+    #[test]
+    fn eases_between_keys_and_holds_the_ends() {
+        let mut script = CameraScript{ease: Ease::Linear, keys: vec![key(10, (0.0, 0.0, -10.0), Some(1.0)), key(20, (10.0, 0.0, -10.0), None)], moves: vec![]};
+        assert!(close(script.shot(0, None).eye, Pnt3::new(0.0, 0.0, -10.0)));
+        assert!(close(script.shot(15, None).eye, Pnt3::new(5.0, 0.0, -10.0)));
+        assert!(close(script.shot(99, None).eye, Pnt3::new(10.0, 0.0, -10.0)));
+        // the second key carries the first one's fov on
+        assert_eq!(script.shot(15, None).fov, 1.0);
+        script.ease = Ease::Smooth;
+        assert!(close(script.shot(12, None).eye, Pnt3::new(1.04, 0.0, -10.0)));
+    }
+
+    #[test]
+    fn frames_the_bounds() {
+        let bounds = (Pnt3::new(-1.0, -1.0, -1.0), Pnt3::new(3.0, 1.0, 1.0));
+        let framed = Key{time: 0, eye: None, at: None, fov: None, frame: None};
+        let script = CameraScript{ease: Ease::Linear, keys: vec![framed], moves: vec![]};
+        let shot = script.shot(5, Some(bounds));
+        assert_eq!((shot.eye, shot.at), framing(bounds));
+        assert_eq!(shot.fov, PI / 4.0);
+        let empty = CameraScript{ease: Ease::Linear, keys: vec![], moves: vec![]};
+        assert_eq!(empty.shot(5, Some(bounds)), shot);
+    }
+
+    #[test]
+    fn moves_swing_and_dolly_the_eye() {
+        let orbit = Move{kind: MoveKind::Orbit, start: 0, end: 10, amount: PI / 2.0};
+        let dolly = Move{kind: MoveKind::Dolly, start: 10, end: 20, amount: 0.5};
+        let script = CameraScript{ease: Ease::Linear, keys: vec![key(0, (0.0, 0.0, -10.0), None)], moves: vec![orbit, dolly]};
+        assert!(close(script.shot(0, None).eye, Pnt3::new(0.0, 0.0, -10.0)));
+        assert!(close(script.shot(10, None).eye, Pnt3::new(10.0, 0.0, 0.0)));
+        assert!(close(script.shot(15, None).eye, Pnt3::new(7.5, 0.0, 0.0)));
+        assert!(close(script.shot(30, None).eye, Pnt3::new(5.0, 0.0, 0.0)));
+    }
+}
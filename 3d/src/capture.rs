@@ -0,0 +1,213 @@
+extern crate nalgebra as na;
+extern crate kiss3d;
+extern crate glfw;
+extern crate image;
+use na::{Pnt3, Vec3, Iso3, Mat4, PerspMat3};
+use std::fs::File;
+use kiss3d::window::Window;
+use kiss3d::camera::Camera;
+use glfw::WindowEvent;
+use camscript::Shot;
+
+const ZNEAR: f32 = 0.1;
+const ZFAR: f32 = 1024.0;
+
+/// The window's size in real pixels, which is what `snap_rect` reads; on
+/// a high DPI display that's bigger than `window.size()`.
+pub fn framebuffer_size(window: &Window) -> (usize, usize) {
+    let (width, height) = window.glfw_window().get_framebuffer_size();
+    (width as usize, height as usize)
+}
+
+/// The whole window as RGB rows, top row first.
+pub fn snap(window: &mut Window) -> (Vec<u8>, usize, usize) {
+    let (width, height) = framebuffer_size(window);
+    let mut buf = Vec::new();
+    window.snap_rect(&mut buf, 0, 0, width, height);
+    vflip(&mut buf, width * 3, height);
+    (buf, width, height)
+}
+
+pub fn vflip(vec: &mut [u8], width: usize, height: usize) {
+    for j in 0 .. height / 2 {
+        for i in 0 .. width {
+            vec.swap((height - j - 1) * width + i, j * width + i);
+        }
+    }
+}
+
+pub fn save_png(buf: Vec<u8>, width: usize, height: usize, outfile: String) {
+    let img = image::ImageBuffer::from_raw(width as u32, height as u32, buf).expect("Create image");
+    let mut fout = File::create(outfile.clone()).ok().expect("Open file");
+    image::ImageRgb8(img).save(&mut fout, image::PNG).ok().expect("Save image");
+    println!("Wrote {}", outfile);
+}
+
+/// Renders one tile of a bigger image: the shot's projection for the full
+/// image size, then scaled and shifted so just this tile fills the window.
+struct TileCamera {
+    eye: Pnt3<f32>,
+    view: Iso3<f32>,
+    projection: Mat4<f32>,
+}
+
+impl TileCamera {
+    // the tile is pixels [x, x + w) by [y, y + h) of a full x full_h image,
+    // counting up from the bottom like GL does
+    fn new(shot: &Shot, full: (usize, usize), x: usize, y: usize, w: usize, h: usize) -> TileCamera {
+        let (full_w, full_h) = (full.0 as f32, full.1 as f32);
+        let mut view: Iso3<f32> = na::one();
+        view.look_at_z(&shot.eye, &shot.at, &Vec3::y());
+        let persp = PerspMat3::new(full_w / full_h, shot.fov, ZNEAR, ZFAR);
+        let (sx, sy) = (full_w / w as f32, full_h / h as f32);
+        let cx = -1.0 + (2.0 * x as f32 + w as f32) / full_w;
+        let cy = -1.0 + (2.0 * y as f32 + h as f32) / full_h;
+        let tile = Mat4::new(
+            sx, 0.0, 0.0, -cx * sx,
+            0.0, sy, 0.0, -cy * sy,
+            0.0, 0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0, 1.0);
+        TileCamera {
+            eye: shot.eye,
+            view: view,
+            projection: tile * *persp.as_mat(),
+        }
+    }
+}
+
+impl Camera for TileCamera {
+    fn handle_event(&mut self, _: &glfw::Window, _: &WindowEvent) {
+    }
+
+    fn eye(&self) -> Pnt3<f32> {
+        self.eye
+    }
+
+    fn view_transform(&self) -> Iso3<f32> {
+        self.view
+    }
+
+    fn transformation(&self) -> Mat4<f32> {
+        self.projection * na::to_homogeneous(&na::inv(&self.view).unwrap())
+    }
+
+    fn inv_transformation(&self) -> Mat4<f32> {
+        na::inv(&self.transformation()).unwrap()
+    }
+
+    fn clip_planes(&self) -> (f32, f32) {
+        (ZNEAR, ZFAR)
+    }
+
+    fn update(&mut self, _: &glfw::Window) {
+    }
+}
+
+/// Render `shot` at `width` x `height`, whatever the window's size or DPI.
+/// This is not offscreen: kiss3d 0.1's `render_with_camera` binds the
+/// window's own framebuffer at the start of every frame, so there's no way
+/// to point it at an FBO of ours without patching kiss3d. Instead the image
+/// is built from window sized tiles, each its own slice of the frustum,
+/// drawn in the window and read back. The window flashes tile by tile while
+/// that happens, and it has to be on screen, uncovered, for the read back
+/// to be right. With
+/// `supersample` above 1 it renders that many times bigger each way and
+/// box filters back down. `draw` queues anything immediate (lines, say)
+/// before each tile, since kiss3d clears them every frame.
+/// Returns RGB rows, top row first.
+pub fn capture(window: &mut Window, shot: &Shot, width: usize, height: usize, supersample: usize, draw: &mut FnMut(&mut Window)) -> Vec<u8> {
+    let ss = if supersample > 0 {supersample} else {1};
+    let (full_w, full_h) = (width * ss, height * ss);
+    let (tile_w, tile_h) = framebuffer_size(window);
+    let mut full = vec![0u8; full_w * full_h * 3];
+
+    let mut y = 0;
+    while y < full_h {
+        let mut x = 0;
+        while x < full_w {
+            let mut camera = TileCamera::new(shot, (full_w, full_h), x, y, tile_w, tile_h);
+            draw(window);
+            window.render_with_camera(&mut camera);
+            let mut buf = Vec::new();
+            window.snap_rect(&mut buf, 0, 0, tile_w, tile_h);
+            // rows come bottom up, same as `y`
+            for row in 0..tile_h {
+                if y + row >= full_h {
+                    break;
+                }
+                let span = (full_w - x).min(tile_w) * 3;
+                let to = ((full_h - 1 - (y + row)) * full_w + x) * 3;
+                let from = row * tile_w * 3;
+                full[to..to + span].clone_from_slice(&buf[from..from + span]);
+            }
+            x += tile_w;
+        }
+        y += tile_h;
+    }
+    downsample(full, width, height, ss)
+}
+
+/// Box filter RGB rows `ss` times bigger each way down to `width` x `height`.
+pub fn downsample(full: Vec<u8>, width: usize, height: usize, ss: usize) -> Vec<u8> {
+    if ss <= 1 {
+        return full;
+    }
+    let full_w = width * ss;
+    let mut out = vec![0u8; width * height * 3];
+    let count = (ss * ss) as u32;
+    for j in 0..height {
+        for i in 0..width {
+            for c in 0..3 {
+                let mut sum = 0u32;
+                for dy in 0..ss {
+                    for dx in 0..ss {
+                        sum += full[((j * ss + dy) * full_w + i * ss + dx) * 3 + c] as u32;
+                    }
+                }
+                out[(j * width + i) * 3 + c] = (sum / count) as u8;
+            }
+        }
+    }
+    out
+}
+
+/// How big to capture at.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Resolution {
+    pub width: usize,
+    pub height: usize,
+    pub supersample: usize,
+}
+
+impl Resolution {
+    /// From a `<width>x<height>` size, like `3840x2160`.
+    pub fn parse(text: &str, supersample: usize) -> Resolution {
+        let parts: Vec<usize> = text.split('x').map(|n| n.trim().parse().ok().expect(&format!("Bad size {}, should be like 3840x2160", text))).collect();
+        if parts.len() != 2 || parts[0] == 0 || parts[1] == 0 {
+            panic!("Bad size {}, should be like 3840x2160", text);
+        }
+        Resolution{width: parts[0], height: parts[1], supersample: supersample}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::downsample;
+
+    #[test]
+    fn averages_each_block() {
+        // 4x2 pixels down to 2x1: a grey block, then a red and blue one
+        let full = vec![
+            10, 10, 10,  20, 20, 20,  255, 0, 0,  0, 0, 255,
+            30, 30, 30,  41, 41, 41,  255, 0, 0,  0, 0, 255,
+        ];
+        assert_eq!(downsample(full, 2, 1, 2), vec![25, 25, 25, 127, 0, 127]);
+    }
+
+    #[test]
+    fn leaves_unsupersampled_rows_alone() {
+        let full = vec![1, 2, 3, 4, 5, 6];
+        assert_eq!(downsample(full.clone(), 2, 1, 1), full);
+        assert_eq!(downsample(full.clone(), 2, 1, 0), full);
+    }
+}
@@ -0,0 +1,140 @@
+use state::State;
+use params::Model;
+use field;
+use real::Real;
+
+impl State {
+    /// The concentration that drives growth and colouring: v for
+    /// Gray-Scott, the activator for Turing, 0 when chemistry is off.
+    pub fn morphogen(&self, i: usize) -> Real {
+        let chem = &self.pts[i].chem;
+        match self.params.chem_model {
+            Model::GrayScott if chem.len() == 2 => chem[1],
+            Model::Turing if chem.len() == 2 => chem[0],
+            _ => 0.0,
+        }
+    }
+
+    // nodes that don't have concentrations yet get the model's resting
+    // state, with a noisy patch around the seed to get patterns going
+    fn seed_chem(&mut self) {
+        let seed = self.seed();
+        let model = self.params.chem_model;
+        for i in 0..self.pts.len() {
+            if self.pts[i].chem.len() == 2 {
+                continue;
+            }
+            let pos = self.pts[i].pos;
+            let jitter = (field::noise(pos.x * 3.0, pos.y * 3.0, pos.z * 3.0, i as u32) + 1.0) / 2.0;
+            let near = (pos.x - seed.x).powi(2) + (pos.y - seed.y).powi(2) + (pos.z - seed.z).powi(2) < 4.0;
+            self.pts[i].chem = match model {
+                Model::GrayScott => if near {vec![0.5, 0.25 + jitter * 0.1]} else {vec![1.0, 0.0]},
+                _ => vec![1.0 + jitter * 0.1, 1.0],
+            };
+        }
+    }
+
+    // graph laplacian along the edges, per component, over each node's
+    // degree: the mean of its neighbours less itself. Splits keep adding
+    // edges to nodes, and a plain sum would make diffusion unstable once
+    // a node had more than 1 / (dt * du) of them.
+    fn laplacian(&self) -> Vec<[Real; 2]> {
+        let mut lap = vec![[0.0, 0.0]; self.pts.len()];
+        let mut degree = vec![0usize; self.pts.len()];
+        for edge in self.edges.iter() {
+            let (a, b) = (&self.pts[edge.a].chem, &self.pts[edge.b].chem);
+            for k in 0..2 {
+                let diff = b[k] - a[k];
+                lap[edge.a][k] += diff;
+                lap[edge.b][k] -= diff;
+            }
+            degree[edge.a] += 1;
+            degree[edge.b] += 1;
+        }
+        for (l, &n) in lap.iter_mut().zip(degree.iter()) {
+            if n > 0 {
+                l[0] /= n as Real;
+                l[1] /= n as Real;
+            }
+        }
+        lap
+    }
+
+    /// Diffuse and react the concentrations for `chem_steps` substeps.
+    pub fn react(&mut self) {
+        self.seed_chem();
+        let (du, dv) = (self.params.chem_du, self.params.chem_dv);
+        let (feed, kill) = (self.params.chem_feed, self.params.chem_kill);
+        let dt = self.params.chem_dt;
+        for _ in 0..self.params.chem_steps {
+            let lap = self.laplacian();
+            for i in 0..self.pts.len() {
+                let (u, v) = (self.pts[i].chem[0], self.pts[i].chem[1]);
+                let (ru, rv) = match self.params.chem_model {
+                    Model::GrayScott => (-u * v * v + feed * (1.0 - u), u * v * v - (feed + kill) * v),
+                    // gierer-meinhardt: u activates itself and v, v inhibits u
+                    _ => (feed * u * u / v.max(1e-4) - kill * u + 0.01, feed * u * u - kill * 2.0 * v),
+                };
+                self.pts[i].chem[0] = (u + dt * (du * lap[i][0] + ru)).max(0.0);
+                self.pts[i].chem[1] = (v + dt * (dv * lap[i][1] + rv)).max(0.0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use params::Model;
+    use state::State;
+    use real::Real;
+    use testing::{node, edge, anchored};
+
+    // a hub joined to `spokes` nodes round it, all at `level` but the hub,
+    // with gray-scott's v at 0 so only u moves, and only by diffusion
+    fn star(spokes: usize, level: Real, hub: Real) -> State {
+        let mut nodes = vec![node(0.0, 0.0, 0.0)];
+        for k in 0..spokes {
+            let angle = k as Real / spokes as Real * 6.283;
+            nodes.push(node(angle.cos(), angle.sin(), 0.0));
+        }
+        let mut state = anchored(nodes);
+        for k in 0..spokes {
+            state.edges.push(edge(10, 11 + k, 1.0));
+            state.edges.push(edge(11 + k, 11 + (k + 1) % spokes, 1.0));
+        }
+        for i in 0..state.pts.len() {
+            state.pts[i].chem = vec![level, 0.0];
+        }
+        state.pts[10].chem[0] = hub;
+        state.params.chem_model = Model::GrayScott;
+        state.params.chem_feed = 0.0;
+        state.params.chem_kill = 0.0;
+        state
+    }
+
+    #[test]
+    fn uniform_stays_uniform() {
+        let mut state = star(12, 0.7, 0.7);
+        state.react();
+        for node in state.pts.iter() {
+            assert!((node.chem[0] - 0.7).abs() < 1e-6);
+            assert_eq!(node.chem[1], 0.0);
+        }
+    }
+
+    #[test]
+    fn spikes_decay_on_busy_nodes() {
+        let mut state = star(12, 1.0, 2.0);
+        let mut last = 2.0;
+        for _ in 0..5 {
+            state.react();
+            let hub = state.pts[10].chem[0];
+            // down every time, and never past the level round it
+            assert!(hub < last && hub > 1.0, "hub went from {} to {}", last, hub);
+            for k in 11..23 {
+                assert!(state.pts[k].chem[0] >= 1.0 && state.pts[k].chem[0] < hub);
+            }
+            last = hub;
+        }
+    }
+}
@@ -0,0 +1,262 @@
+extern crate nalgebra as na;
+use na::{Pnt3, Vec3, Norm};
+use state::State;
+use grid::Grid;
+use real::Real;
+
+// closest points between segments p1-q1 and p2-q2, as fractions along each
+// (Ericson, Real-Time Collision Detection 5.1.9)
+fn segment_params(p1: Pnt3<Real>, q1: Pnt3<Real>, p2: Pnt3<Real>, q2: Pnt3<Real>) -> (Real, Real) {
+    let d1 = q1 - p1;
+    let d2 = q2 - p2;
+    let r = p1 - p2;
+    let a = na::dot(&d1, &d1);
+    let e = na::dot(&d2, &d2);
+    let f = na::dot(&d2, &r);
+    if a < 1e-12 || e < 1e-12 {
+        return (0.0, 0.0);
+    }
+    let c = na::dot(&d1, &r);
+    let b = na::dot(&d1, &d2);
+    let denom = a * e - b * b;
+    let mut s = if denom > 1e-12 {((b * f - c * e) / denom).max(0.0).min(1.0)} else {0.0};
+    let mut t = (b * s + f) / e;
+    if t < 0.0 {
+        t = 0.0;
+        s = (-c / a).max(0.0).min(1.0);
+    } else if t > 1.0 {
+        t = 1.0;
+        s = ((b - c) / a).max(0.0).min(1.0);
+    }
+    (s, t)
+}
+
+// barycentric coordinates of p (assumed in-plane) in triangle abc
+fn barycentric(p: Pnt3<Real>, a: Pnt3<Real>, b: Pnt3<Real>, c: Pnt3<Real>) -> Option<(Real, Real, Real)> {
+    let v0 = b - a;
+    let v1 = c - a;
+    let v2 = p - a;
+    let d00 = na::dot(&v0, &v0);
+    let d01 = na::dot(&v0, &v1);
+    let d11 = na::dot(&v1, &v1);
+    let d20 = na::dot(&v2, &v0);
+    let d21 = na::dot(&v2, &v1);
+    let denom = d00 * d11 - d01 * d01;
+    if denom.abs() < 1e-12 {
+        return None;
+    }
+    let v = (d11 * d20 - d01 * d21) / denom;
+    let w = (d00 * d21 - d01 * d20) / denom;
+    Some((1.0 - v - w, v, w))
+}
+
+impl State {
+    /// Discrete self-collision: keeps vertices `collide_dist` clear of the
+    /// triangles round them and edges that far clear of each other, sending
+    /// anything that passed through since the last tick back the way it
+    /// came. Returns the number of those crossings.
+    pub fn collide(&mut self) -> usize {
+        // where everything was a tick ago, near enough, to tell which side
+        // of things it came from
+        let dt = self.params.dt;
+        let prev: Vec<Pnt3<Real>> = self.pts.iter().map(|n| n.pos - n.vel * dt).collect();
+        self.collide_vertex_tri(&prev) + self.collide_edge_edge(&prev)
+    }
+
+    fn collide_vertex_tri(&mut self, prev: &[Pnt3<Real>]) -> usize {
+        let thick = self.params.collide_dist;
+        let grid = Grid::new(&self.coords(), self.params.close_dist * 2.0);
+        let mut crossed = 0;
+        for t in 0..self.tris.len() {
+            let Pnt3{x: a, y: b, z: c} = self.tris[t];
+            let (a, b, c) = (a as usize, b as usize, c as usize);
+            let centroid = self.pts[a].pos + ((self.pts[b].pos - self.pts[a].pos) + (self.pts[c].pos - self.pts[a].pos)) / 3.0;
+            for j in grid.around(&centroid) {
+                if j == a || j == b || j == c {
+                    continue;
+                }
+                let (pa, pb, pc) = (self.pts[a].pos, self.pts[b].pos, self.pts[c].pos);
+                let normal = na::cross(&(pb - pa), &(pc - pa));
+                if normal.norm() < 1e-12 {
+                    break;
+                }
+                let normal = normal.normalize();
+                let p = self.pts[j].pos;
+                let depth = na::dot(&(p - pa), &normal);
+                if depth.abs() >= thick {
+                    continue;
+                }
+                let (u, v, w) = match barycentric(p - normal * depth, pa, pb, pc) {
+                    Some(coords) => coords,
+                    None => continue,
+                };
+                if u < 0.0 || v < 0.0 || w < 0.0 {
+                    continue;
+                }
+                // the side it was on a tick ago, of the triangle as it was then
+                let then = na::cross(&(prev[b] - prev[a]), &(prev[c] - prev[a]));
+                let was = na::dot(&(prev[j] - prev[a]), &then);
+                let through = was * depth < 0.0;
+                let side = if through {
+                    if was < 0.0 {-1.0} else {1.0}
+                } else if depth < 0.0 {-1.0} else {1.0};
+                let push = normal * (side * thick - depth) / 2.0;
+                self.nudge(j, push);
+                self.nudge(a, -push * u);
+                self.nudge(b, -push * v);
+                self.nudge(c, -push * w);
+                // drop the velocity heading back into the sheet
+                let into = na::dot(&self.pts[j].vel, &normal) * side;
+                if into < 0.0 && !self.pts[j].pinned {
+                    self.pts[j].vel = self.pts[j].vel - normal * side * into;
+                }
+                if through {
+                    crossed += 1;
+                }
+            }
+        }
+        crossed
+    }
+
+    fn collide_edge_edge(&mut self, prev: &[Pnt3<Real>]) -> usize {
+        let thick = self.params.collide_dist;
+        let mids: Vec<Pnt3<Real>> = self.edges.iter().map(|e|
+            self.pts[e.a].pos + (self.pts[e.b].pos - self.pts[e.a].pos) / 2.0
+        ).collect();
+        let grid = Grid::new(&mids, self.params.close_dist * 2.0);
+        let mut crossed = 0;
+        for i in 0..self.edges.len() {
+            let (a1, b1) = self.edge_pts(i);
+            for k in grid.around(&mids[i]) {
+                if k <= i {
+                    continue;
+                }
+                let (a2, b2) = self.edge_pts(k);
+                if a1 == a2 || a1 == b2 || b1 == a2 || b1 == b2 {
+                    continue;
+                }
+                let (p1, q1, p2, q2) = (self.pts[a1].pos, self.pts[b1].pos, self.pts[a2].pos, self.pts[b2].pos);
+                let (s, t) = segment_params(p1, q1, p2, q2);
+                let c1 = p1 + (q1 - p1) * s;
+                let c2 = p2 + (q2 - p2) * t;
+                let between: Vec3<Real> = c2 - c1;
+                let dist = between.norm();
+                if dist >= thick {
+                    continue;
+                }
+                // which side of the first edge the second is on, along their
+                // common normal, now and a tick ago; a flip with the closest
+                // points inside both edges means one went through the other
+                let across = na::cross(&(q1 - p1), &(q2 - p2));
+                let then = na::cross(&(prev[b1] - prev[a1]), &(prev[b2] - prev[a2]));
+                let was = na::dot(&(prev[a2] - prev[a1]), &then);
+                let now = na::dot(&(p2 - p1), &across);
+                let inside = s > 0.0 && s < 1.0 && t > 0.0 && t < 1.0;
+                let through = inside && (dist < 1e-9 || was * now < 0.0);
+                let push = if through || dist < 1e-9 {
+                    // touching, so `between` has no direction to go by
+                    if across.norm() < 1e-12 {
+                        continue;
+                    }
+                    let normal = across.normalize();
+                    let side = if was < 0.0 {-1.0} else {1.0};
+                    normal * (side * thick - na::dot(&between, &normal)) / 2.0
+                } else {
+                    between.normalize() * (thick - dist) / 2.0
+                };
+                self.nudge(a1, -push * (1.0 - s));
+                self.nudge(b1, -push * s);
+                self.nudge(a2, push * (1.0 - t));
+                self.nudge(b2, push * t);
+                if through {
+                    crossed += 1;
+                }
+            }
+        }
+        crossed
+    }
+
+    // pinned nodes stay put and leave the others to move out of the way
+    fn nudge(&mut self, i: usize, by: Vec3<Real>) {
+        if !self.pts[i].pinned {
+            self.pts[i].pos = self.pts[i].pos + by;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::segment_params;
+    use na::{Pnt3, Vec3};
+    use state::State;
+    use real::Real;
+    use testing::{node, edge, anchored};
+
+    type Pt = (Real, Real, Real);
+
+    fn params(p1: Pt, q1: Pt, p2: Pt, q2: Pt) -> (Real, Real) {
+        let pnt = |p: Pt| Pnt3::new(p.0, p.1, p.2);
+        segment_params(pnt(p1), pnt(q1), pnt(p2), pnt(q2))
+    }
+
+    #[test]
+    fn finds_the_closest_points() {
+        // crossing over each other, one above
+        assert_eq!(params((-1.0, 0.0, 0.0), (1.0, 0.0, 0.0), (0.0, -1.0, 1.0), (0.0, 1.0, 1.0)), (0.5, 0.5));
+        // the second edge off the end of the first
+        assert_eq!(params((0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (2.0, -1.0, 0.0), (2.0, 1.0, 0.0)), (1.0, 0.5));
+        // parallel, and a point
+        assert_eq!(params((0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (0.0, 1.0, 0.0), (1.0, 1.0, 0.0)), (0.0, 0.0));
+        assert_eq!(params((0.0, 0.0, 0.0), (0.0, 0.0, 0.0), (0.0, 1.0, 0.0), (1.0, 1.0, 0.0)), (0.0, 0.0));
+    }
+
+    // a big triangle flat at z 0 and a node over it at `z`, moving at `vz`
+    fn over_a_triangle(z: Real, vz: Real) -> State {
+        let mut probe = node(0.2, 0.2, z);
+        probe.vel = Vec3::new(0.0, 0.0, vz);
+        let mut state = anchored(vec![node(-1.0, -1.0, 0.0), node(2.0, -1.0, 0.0), node(-1.0, 2.0, 0.0), probe]);
+        state.tris.push(Pnt3::new(10, 11, 12));
+        state.params.collide_dist = 0.1;
+        state
+    }
+
+    #[test]
+    fn keeps_a_near_vertex_clear_without_counting_it() {
+        let mut state = over_a_triangle(0.05, 0.0);
+        assert_eq!(state.collide(), 0);
+        assert!(state.pts[13].pos.z > 0.05);
+        assert!(state.pts[10].pos.z < 0.0);
+    }
+
+    #[test]
+    fn sends_a_vertex_back_through() {
+        // at -0.05 now, having come down from 0.05
+        let mut state = over_a_triangle(-0.05, -0.1);
+        assert_eq!(state.collide(), 1);
+        assert!(state.pts[13].pos.z > state.pts[10].pos.z);
+        assert!(state.pts[13].vel.z >= 0.0);
+    }
+
+    fn crossed_edges(z: Real) -> State {
+        let mut state = anchored(vec![node(-1.0, 0.0, 0.0), node(1.0, 0.0, 0.0), node(0.0, -1.0, z), node(0.0, 1.0, z)]);
+        state.edges.push(edge(10, 11, 2.0));
+        state.edges.push(edge(12, 13, 2.0));
+        state.params.collide_dist = 0.1;
+        state
+    }
+
+    #[test]
+    fn parts_edges_that_cross() {
+        let mut state = crossed_edges(0.0);
+        assert_eq!(state.collide(), 1);
+        let gap = state.pts[12].pos.z - state.pts[10].pos.z;
+        assert!(gap.abs() > 0.01);
+    }
+
+    #[test]
+    fn parts_near_edges_without_counting_them() {
+        let mut state = crossed_edges(0.05);
+        assert_eq!(state.collide(), 0);
+        assert!(state.pts[12].pos.z - state.pts[10].pos.z > 0.05);
+    }
+}
@@ -0,0 +1,66 @@
+extern crate nalgebra as na;
+use na::Pnt2;
+use state::State;
+
+/// How the mesh gets coloured. `Age` is the shader's own age/trunk look,
+/// the rest are a value in [0, 1] shown as a heat map.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColorScheme {
+    Age,
+    Chem,
+    Exposure,
+    Region,
+}
+
+impl ColorScheme {
+    pub fn from_name(name: &str) -> Option<ColorScheme> {
+        match name {
+            "age" => Some(ColorScheme::Age),
+            "chem" => Some(ColorScheme::Chem),
+            "exposure" => Some(ColorScheme::Exposure),
+            "region" => Some(ColorScheme::Region),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ColorScheme::Age => "age",
+            ColorScheme::Chem => "chem",
+            ColorScheme::Exposure => "exposure",
+            ColorScheme::Region => "region",
+        }
+    }
+
+    pub fn next(&self) -> ColorScheme {
+        match *self {
+            ColorScheme::Age => ColorScheme::Chem,
+            ColorScheme::Chem => ColorScheme::Exposure,
+            ColorScheme::Exposure => ColorScheme::Region,
+            ColorScheme::Region => ColorScheme::Age,
+        }
+    }
+}
+
+fn heat(vals: Vec<f32>) -> Vec<Pnt2<f32>> {
+    let max = vals.iter().fold(0.0f32, |max, v| max.max(*v));
+    heat_over(&vals, max)
+}
+
+/// Heat map colours scaled so `max` is the hottest, for meshes that need
+/// to share a scale. uvs.y of 2 tells the shader to use the heat map.
+pub fn heat_over(vals: &[f32], max: f32) -> Vec<Pnt2<f32>> {
+    let scale = if max > 0.0 {1.0 / max} else {0.0};
+    vals.iter().map(|v| Pnt2::new((v * scale).min(1.0), 2.0)).collect()
+}
+
+impl State {
+    pub fn scheme_colors(&self, scheme: ColorScheme) -> Vec<Pnt2<f32>> {
+        match scheme {
+            ColorScheme::Age => self.coord_colors(0.0),
+            ColorScheme::Chem => heat((0..self.pts.len()).map(|i| self.morphogen(i) as f32).collect()),
+            ColorScheme::Exposure => heat(self.pts.iter().map(|n| n.exposure as f32).collect()),
+            ColorScheme::Region => heat(self.pts.iter().map(|n| n.region as f32).collect()),
+        }
+    }
+}
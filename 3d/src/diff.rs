@@ -0,0 +1,76 @@
+extern crate nalgebra as na;
+use na::FloatPnt;
+use state::State;
+use grid::Grid;
+use real::{self, Real};
+
+/// How to measure one state against another.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Diff {
+    /// Node i against node i. Splits only ever append nodes, so this lines
+    /// up for states from the same run, as long as remeshing hasn't
+    /// renumbered them.
+    Node,
+    /// Each node against the nearest node of the other state.
+    Surface,
+}
+
+impl Diff {
+    pub fn from_name(name: &str) -> Option<Diff> {
+        match name {
+            "node" => Some(Diff::Node),
+            "surface" => Some(Diff::Surface),
+            _ => None,
+        }
+    }
+}
+
+impl State {
+    /// How far each node is from `reference`. With `Node`, nodes past the
+    /// end of `reference` are measured as with `Surface`.
+    pub fn distances(&self, reference: &State, diff: Diff) -> Vec<Real> {
+        let coords = reference.coords();
+        let size = reference.params.max_len * 4.0;
+        let grid = Grid::new(&coords, size);
+        self.pts.iter().enumerate().map(|(i, node)| {
+            if diff == Diff::Node && i < coords.len() {
+                return node.pos.dist(&coords[i]);
+            }
+            let near = grid.around(&node.pos);
+            // nothing close by, so look everywhere
+            let candidates: Vec<usize> = if near.is_empty() {(0..coords.len()).collect()} else {near};
+            candidates.iter().fold(real::INFINITY, |best, &j| best.min(node.pos.dist(&coords[j])))
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Diff;
+    use na::Vec3;
+    use real::Real;
+    use testing::{node, anchored};
+
+    #[test]
+    fn measures_a_translated_copy() {
+        let reference = anchored(vec![node(0.0, 0.0, 0.0), node(1.0, 0.0, 0.0), node(0.0, 1.0, 0.0)]);
+        let mut moved = reference.clone();
+        for node in moved.pts.iter_mut() {
+            node.pos = node.pos + Vec3::new(0.0, 0.0, 0.25);
+        }
+        for &diff in [Diff::Node, Diff::Surface].iter() {
+            let distances = moved.distances(&reference, diff);
+            assert_eq!(distances.len(), 13);
+            assert!(distances.iter().all(|&d| (d - 0.25).abs() < 1e-6), "{:?}: {:?}", diff, distances);
+        }
+    }
+
+    #[test]
+    fn measures_new_nodes_against_the_surface() {
+        let reference = anchored(vec![node(0.0, 0.0, 0.0), node(1.0, 0.0, 0.0)]);
+        let mut grown = reference.clone();
+        grown.pts.push(node(0.5, 0.0, 0.0));
+        let distances = grown.distances(&reference, Diff::Node);
+        assert_eq!(distances[12], 0.5 as Real);
+    }
+}
@@ -0,0 +1,184 @@
+extern crate nalgebra as na;
+use na::{Pnt3, Vec4, Mat4, ToHomogeneous};
+use std::cmp::Ordering;
+use state::State;
+
+/// Edges fade toward this, the background of both the window and images.
+const BACKGROUND: Pnt3<f32> = Pnt3{x: 1.0, y: 1.0, z: 1.0};
+
+/// How the hollow views draw edges.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct EdgeStyle {
+    /// How far the farthest edges fade toward the background, 0 to 1.
+    /// In software images they also thin, down to half width at 1; GL
+    /// draws every line the same width, so there they only fade.
+    pub cue: f32,
+    /// Leave out edges the surface hides.
+    pub hidden: bool,
+}
+
+impl EdgeStyle {
+    /// Every edge the same, as they were before depth cueing.
+    pub fn plain() -> EdgeStyle {
+        EdgeStyle{cue: 0.0, hidden: false}
+    }
+}
+
+/// An edge ready to draw, with `far` from 0 for the nearest to 1 for the
+/// farthest.
+pub struct DepthEdge {
+    pub a: Pnt3<f32>,
+    pub b: Pnt3<f32>,
+    pub color: Pnt3<f32>,
+    pub far: f32,
+}
+
+impl State {
+    /// The edges as seen from `eye`, farthest first so nearer ones draw over
+    /// them, coloured by `edge_color` and faded by depth as `style` asks.
+    pub fn depth_edges(&self, eye: &Pnt3<f32>, off: f32, style: &EdgeStyle) -> Vec<DepthEdge> {
+        let mut order: Vec<(f32, usize)> = (0..self.num_edges()).map(|i| {
+            let (a, b) = self.edge_pts(i);
+            let (pa, pb) = (self.render_pos(a), self.render_pos(b));
+            let mid = Pnt3::new((pa.x + pb.x) / 2.0, (pa.y + pb.y) / 2.0, (pa.z + pb.z) / 2.0);
+            (na::norm(&(mid - *eye)), i)
+        }).collect();
+        order.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+
+        let (far, near) = match (order.first(), order.last()) {
+            (Some(first), Some(last)) => (first.0, last.0),
+            _ => return vec![],
+        };
+        let span = if far > near {far - near} else {1.0};
+        order.iter().map(|&(dist, i)| {
+            let (a, b) = self.edge_pts(i);
+            let depth = (dist - near) / span;
+            let color = self.edge_color(i, off);
+            DepthEdge {
+                a: self.render_pos(a),
+                b: self.render_pos(b),
+                color: color + (BACKGROUND - color) * (depth * style.cue),
+                far: depth,
+            }
+        }).collect()
+    }
+}
+
+/// A software depth buffer of the surface, for hidden-line removal in
+/// images. Holds 1/w per pixel, bigger being nearer, which unlike w
+/// interpolates linearly across a projected triangle.
+pub struct ZBuffer {
+    width: usize,
+    height: usize,
+    depth: Vec<f32>,
+}
+
+/// A point in pixels (y down) and its 1/w, or None if it's behind the eye.
+pub fn to_pixel(proj_view: &Mat4<f32>, p: &Pnt3<f32>, width: f32, height: f32) -> Option<(f32, f32, f32)> {
+    let Vec4{x, y, w, ..} = *proj_view * p.to_homogeneous();
+    if w <= 0.0 {
+        return None;
+    }
+    Some(((x / w + 1.0) / 2.0 * width, (1.0 - y / w) / 2.0 * height, 1.0 / w))
+}
+
+impl ZBuffer {
+    pub fn new(state: &State, proj_view: &Mat4<f32>, width: usize, height: usize) -> ZBuffer {
+        let mut zbuf = ZBuffer{width: width, height: height, depth: vec![0.0; width * height]};
+        let (w, h) = (width as f32, height as f32);
+        for tri in state.tris.iter() {
+            let corners = (
+                to_pixel(proj_view, &state.render_pos(tri.x as usize), w, h),
+                to_pixel(proj_view, &state.render_pos(tri.y as usize), w, h),
+                to_pixel(proj_view, &state.render_pos(tri.z as usize), w, h),
+            );
+            if let (Some(a), Some(b), Some(c)) = corners {
+                zbuf.fill(a, b, c);
+            }
+        }
+        zbuf
+    }
+
+    fn fill(&mut self, a: (f32, f32, f32), b: (f32, f32, f32), c: (f32, f32, f32)) {
+        let area = (b.0 - a.0) * (c.1 - a.1) - (c.0 - a.0) * (b.1 - a.1);
+        if area.abs() < 1e-6 {
+            return;
+        }
+        let left = a.0.min(b.0).min(c.0).max(0.0) as usize;
+        let right = a.0.max(b.0).max(c.0).min(self.width as f32 - 1.0);
+        let top = a.1.min(b.1).min(c.1).max(0.0) as usize;
+        let bottom = a.1.max(b.1).max(c.1).min(self.height as f32 - 1.0);
+        if right < 0.0 || bottom < 0.0 {
+            return;
+        }
+        for y in top..bottom as usize + 1 {
+            for x in left..right as usize + 1 {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                let wa = ((b.0 - px) * (c.1 - py) - (c.0 - px) * (b.1 - py)) / area;
+                let wb = ((c.0 - px) * (a.1 - py) - (a.0 - px) * (c.1 - py)) / area;
+                let wc = 1.0 - wa - wb;
+                if wa < 0.0 || wb < 0.0 || wc < 0.0 {
+                    continue;
+                }
+                let z = wa * a.2 + wb * b.2 + wc * c.2;
+                let at = y * self.width + x;
+                if z > self.depth[at] {
+                    self.depth[at] = z;
+                }
+            }
+        }
+    }
+
+    /// Whether a point at pixel (`x`, `y`) with 1/w `z` is in front of the
+    /// surface. The slack keeps edges lying on the surface visible.
+    pub fn visible(&self, x: f32, y: f32, z: f32) -> bool {
+        if x < 0.0 || y < 0.0 || x >= self.width as f32 || y >= self.height as f32 {
+            return true;
+        }
+        let at = y as usize * self.width + x as usize;
+        z * 1.01 >= self.depth[at]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ZBuffer, to_pixel};
+    use na::{self, Pnt3, Mat4};
+
+    fn blank(width: usize, height: usize) -> ZBuffer {
+        ZBuffer{width: width, height: height, depth: vec![0.0; width * height]}
+    }
+
+    #[test]
+    fn projects_to_pixels_y_down() {
+        let identity: Mat4<f32> = na::one();
+        assert_eq!(to_pixel(&identity, &Pnt3::new(0.5, 0.5, 0.0), 100.0, 50.0), Some((75.0, 12.5, 1.0)));
+        assert_eq!(to_pixel(&identity, &Pnt3::new(-1.0, -1.0, 0.0), 100.0, 50.0), Some((0.0, 50.0, 1.0)));
+    }
+
+    #[test]
+    fn hides_only_what_is_behind_the_fill() {
+        let mut zbuf = blank(10, 10);
+        zbuf.fill((0.0, 0.0, 0.5), (10.0, 0.0, 0.5), (0.0, 10.0, 0.5));
+        // inside the triangle, behind, on and in front of it
+        assert!(!zbuf.visible(2.5, 2.5, 0.4));
+        assert!(zbuf.visible(2.5, 2.5, 0.5));
+        assert!(zbuf.visible(2.5, 2.5, 0.6));
+        // past its long side, and off the buffer
+        assert!(zbuf.visible(8.5, 8.5, 0.1));
+        assert!(zbuf.visible(-1.0, 2.5, 0.1));
+        assert!(zbuf.visible(2.5, 10.0, 0.1));
+    }
+
+    #[test]
+    fn keeps_the_nearest_fill() {
+        let mut zbuf = blank(4, 4);
+        zbuf.fill((0.0, 0.0, 0.8), (4.0, 0.0, 0.8), (0.0, 4.0, 0.8));
+        zbuf.fill((0.0, 0.0, 0.2), (4.0, 0.0, 0.2), (0.0, 4.0, 0.2));
+        assert!(!zbuf.visible(0.5, 0.5, 0.5));
+        // wound the other way round fills the same
+        let mut flipped = blank(4, 4);
+        flipped.fill((0.0, 0.0, 0.8), (0.0, 4.0, 0.8), (4.0, 0.0, 0.8));
+        assert!(!flipped.visible(0.5, 0.5, 0.5));
+    }
+}
@@ -0,0 +1,349 @@
+extern crate nalgebra as na;
+use na::{Pnt3, FloatPnt};
+use std::fs::File;
+use std::io::Read;
+use real::Real;
+
+/// A parsed arithmetic expression over `x`, `y`, `z` and `r` (distance from
+/// the origin).
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone, Debug)]
+pub enum Expr {
+    Num(Real),
+    Var(char),
+    Neg(Box<Expr>),
+    Bin(char, Box<Expr>, Box<Expr>),
+    Call(String, Vec<Expr>),
+}
+
+/// A voxel grid of values, sampled at the nearest cell.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone, Debug)]
+pub struct Voxels {
+    pub dims: (usize, usize, usize),
+    pub min: (Real, Real, Real),
+    pub cell: Real,
+    pub data: Vec<Real>,
+}
+
+/// A scalar value over space, used to scale growth params per edge.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone, Debug)]
+pub enum Field {
+    Const(Real),
+    Expr(Expr),
+    Noise{scale: Real, amp: Real, seed: u32},
+    SeedDistance{scale: Real, offset: Real},
+    Voxels(Voxels),
+}
+
+/// The fields modulating growth. Each one is a multiplier, evaluated at
+/// edge midpoints; `None` leaves the param alone.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone, Debug)]
+pub struct Fields {
+    pub growth: Option<Field>,
+    pub max_len: Option<Field>,
+    pub stiffness: Option<Field>,
+}
+
+impl Fields {
+    pub fn none() -> Fields {
+        Fields {
+            growth: None,
+            max_len: None,
+            stiffness: None,
+        }
+    }
+}
+
+impl Field {
+    /// Parse a field from the command line. One of:
+    /// `<number>`, `expr:<expression>`, `noise:<scale>,<amp>[,<seed>]`,
+    /// `seed:<scale>[,<offset>]` or `voxels:<path>`.
+    pub fn parse(spec: &str) -> Result<Field, String> {
+        let (kind, rest) = match spec.find(':') {
+            Some(at) => (&spec[..at], &spec[at + 1..]),
+            None => return spec.trim().parse().map(Field::Const).map_err(|_| format!("Bad field {}", spec)),
+        };
+        let nums = || -> Result<Vec<Real>, String> {
+            rest.split(',').map(|n| n.trim().parse().map_err(|_| format!("Bad number {} in {}", n, spec))).collect()
+        };
+        match kind {
+            "expr" => Parser::new(rest).parse().map(Field::Expr),
+            "noise" => {
+                let nums = try!(nums());
+                if nums.len() < 2 {
+                    return Err(format!("noise needs a scale and amplitude: {}", spec));
+                }
+                Ok(Field::Noise{scale: nums[0], amp: nums[1], seed: if nums.len() > 2 {nums[2] as u32} else {0}})
+            },
+            "seed" => {
+                let nums = try!(nums());
+                Ok(Field::SeedDistance{scale: nums[0], offset: if nums.len() > 1 {nums[1]} else {1.0}})
+            },
+            "voxels" => load_voxels(rest).map(Field::Voxels),
+            _ => Err(format!("Unknown field kind {}", kind)),
+        }
+    }
+
+    pub fn eval(&self, pos: &Pnt3<Real>, seed: &Pnt3<Real>) -> Real {
+        match *self {
+            Field::Const(val) => val,
+            Field::Expr(ref expr) => expr.eval(pos),
+            Field::Noise{scale, amp, seed: salt} => 1.0 + amp * noise(pos.x * scale, pos.y * scale, pos.z * scale, salt),
+            Field::SeedDistance{scale, offset} => offset + scale * pos.dist(seed),
+            Field::Voxels(ref voxels) => voxels.sample(pos),
+        }
+    }
+}
+
+impl Expr {
+    pub fn eval(&self, pos: &Pnt3<Real>) -> Real {
+        match *self {
+            Expr::Num(val) => val,
+            Expr::Var('x') => pos.x,
+            Expr::Var('y') => pos.y,
+            Expr::Var('z') => pos.z,
+            Expr::Var(_) => (pos.x * pos.x + pos.y * pos.y + pos.z * pos.z).sqrt(),
+            Expr::Neg(ref inner) => -inner.eval(pos),
+            Expr::Bin(op, ref a, ref b) => {
+                let (a, b) = (a.eval(pos), b.eval(pos));
+                match op {
+                    '+' => a + b,
+                    '-' => a - b,
+                    '*' => a * b,
+                    '/' => a / b,
+                    _ => a.powf(b),
+                }
+            },
+            Expr::Call(ref name, ref args) => {
+                let args: Vec<Real> = args.iter().map(|a| a.eval(pos)).collect();
+                match &name[..] {
+                    "sin" => args[0].sin(),
+                    "cos" => args[0].cos(),
+                    "abs" => args[0].abs(),
+                    "sqrt" => args[0].sqrt(),
+                    "exp" => args[0].exp(),
+                    "min" => args[0].min(args[1]),
+                    _ => args[0].max(args[1]),
+                }
+            },
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    at: usize,
+}
+
+impl Parser {
+    fn new(src: &str) -> Parser {
+        Parser{chars: src.chars().filter(|c| !c.is_whitespace()).collect(), at: 0}
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.at).cloned()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.at += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse(&mut self) -> Result<Expr, String> {
+        let expr = try!(self.sum());
+        if self.at < self.chars.len() {
+            return Err(format!("Unexpected {} in expression", self.chars[self.at]));
+        }
+        Ok(expr)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut left = try!(self.product());
+        loop {
+            let op = match self.peek() {
+                Some(c) if c == '+' || c == '-' => c,
+                _ => return Ok(left),
+            };
+            self.at += 1;
+            let right = try!(self.product());
+            left = Expr::Bin(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut left = try!(self.power());
+        loop {
+            let op = match self.peek() {
+                Some(c) if c == '*' || c == '/' => c,
+                _ => return Ok(left),
+            };
+            self.at += 1;
+            let right = try!(self.power());
+            left = Expr::Bin(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn power(&mut self) -> Result<Expr, String> {
+        let base = try!(self.unary());
+        if self.eat('^') {
+            let exp = try!(self.power());
+            return Ok(Expr::Bin('^', Box::new(base), Box::new(exp)));
+        }
+        Ok(base)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat('-') {
+            return Ok(Expr::Neg(Box::new(try!(self.unary()))));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        if self.eat('(') {
+            let inner = try!(self.sum());
+            if !self.eat(')') {
+                return Err("Missing )".to_string());
+            }
+            return Ok(inner);
+        }
+        let start = self.at;
+        match self.peek() {
+            Some(c) if c.is_digit(10) || c == '.' => {
+                while self.peek().map_or(false, |c| c.is_digit(10) || c == '.') {
+                    self.at += 1;
+                }
+                let text: String = self.chars[start..self.at].iter().cloned().collect();
+                text.parse().map(Expr::Num).map_err(|_| format!("Bad number {}", text))
+            },
+            Some(c) if c.is_alphabetic() => {
+                while self.peek().map_or(false, |c| c.is_alphabetic()) {
+                    self.at += 1;
+                }
+                let name: String = self.chars[start..self.at].iter().cloned().collect();
+                match &name[..] {
+                    "x" | "y" | "z" | "r" => return Ok(Expr::Var(name.chars().next().unwrap())),
+                    "sin" | "cos" | "abs" | "sqrt" | "exp" | "min" | "max" => {},
+                    _ => return Err(format!("Unknown name {}", name)),
+                }
+                if !self.eat('(') {
+                    return Err(format!("{} needs arguments", name));
+                }
+                let mut args = vec![try!(self.sum())];
+                while self.eat(',') {
+                    args.push(try!(self.sum()));
+                }
+                if !self.eat(')') {
+                    return Err("Missing )".to_string());
+                }
+                let want = if name == "min" || name == "max" {2} else {1};
+                if args.len() != want {
+                    return Err(format!("{} takes {} arguments", name, want));
+                }
+                Ok(Expr::Call(name, args))
+            },
+            Some(c) => Err(format!("Unexpected {} in expression", c)),
+            None => Err("Expression ended early".to_string()),
+        }
+    }
+}
+
+// integer lattice hash, to [0, 1]
+fn hash(x: i32, y: i32, z: i32, seed: u32) -> Real {
+    let mut h = (x as u32).wrapping_mul(73856093) ^ (y as u32).wrapping_mul(19349663) ^ (z as u32).wrapping_mul(83492791) ^ seed.wrapping_mul(2654435761);
+    h = (h ^ (h >> 13)).wrapping_mul(1274126177);
+    h = h ^ (h >> 16);
+    (h & 0xffffff) as Real / 0xffffff as Real
+}
+
+/// Smoothed value noise, in [-1, 1].
+pub fn noise(x: Real, y: Real, z: Real, seed: u32) -> Real {
+    let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+    let smooth = |t: Real| t * t * (3.0 - 2.0 * t);
+    let (fx, fy, fz) = (smooth(x - x0), smooth(y - y0), smooth(z - z0));
+    let (ix, iy, iz) = (x0 as i32, y0 as i32, z0 as i32);
+    let lerp = |a: Real, b: Real, t: Real| a + (b - a) * t;
+    let corner = |dx: i32, dy: i32, dz: i32| hash(ix + dx, iy + dy, iz + dz, seed);
+    let v = lerp(
+        lerp(lerp(corner(0, 0, 0), corner(1, 0, 0), fx), lerp(corner(0, 1, 0), corner(1, 1, 0), fx), fy),
+        lerp(lerp(corner(0, 0, 1), corner(1, 0, 1), fx), lerp(corner(0, 1, 1), corner(1, 1, 1), fx), fy),
+        fz);
+    v * 2.0 - 1.0
+}
+
+impl Voxels {
+    pub fn sample(&self, pos: &Pnt3<Real>) -> Real {
+        let (nx, ny, nz) = self.dims;
+        let index = |v: Real, min: Real, n: usize| (((v - min) / self.cell).floor().max(0.0) as usize).min(n - 1);
+        let (x, y, z) = (index(pos.x, self.min.0, nx), index(pos.y, self.min.1, ny), index(pos.z, self.min.2, nz));
+        self.data[(z * ny + y) * nx + x]
+    }
+}
+
+// whitespace separated: nx ny nz, then minx miny minz, then the cell size,
+// then nx * ny * nz values with x varying fastest
+fn load_voxels(path: &str) -> Result<Voxels, String> {
+    let mut text = String::new();
+    try!(File::open(path).and_then(|mut f| f.read_to_string(&mut text)).map_err(|e| format!("Can't read {}: {}", path, e)));
+    let nums: Vec<Real> = try!(text.split_whitespace().map(|n| n.parse().map_err(|_| format!("Bad number {} in {}", n, path))).collect());
+    if nums.len() < 7 {
+        return Err(format!("Voxel file {} is missing its header", path));
+    }
+    let dims = (nums[0] as usize, nums[1] as usize, nums[2] as usize);
+    let data = nums[7..].to_vec();
+    if dims.0 == 0 || dims.1 == 0 || dims.2 == 0 || data.len() != dims.0 * dims.1 * dims.2 {
+        return Err(format!("Voxel file {} should have {}x{}x{} values", path, dims.0, dims.1, dims.2));
+    }
+    Ok(Voxels{dims: dims, min: (nums[3], nums[4], nums[5]), cell: nums[6], data: data})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Field, Expr, Parser};
+    use na::Pnt3;
+    use real::Real;
+
+    fn eval(src: &str, x: Real, y: Real, z: Real) -> Real {
+        Parser::new(src).parse().unwrap().eval(&Pnt3::new(x, y, z))
+    }
+
+    #[test]
+    fn binds_tighter_to_the_right() {
+        assert_eq!(eval("1 + 2 * 3", 0.0, 0.0, 0.0), 7.0);
+        assert_eq!(eval("(1 + 2) * 3", 0.0, 0.0, 0.0), 9.0);
+        assert_eq!(eval("8 - 2 - 1", 0.0, 0.0, 0.0), 5.0);
+        assert_eq!(eval("2 ^ 3 ^ 2", 0.0, 0.0, 0.0), 512.0);
+        assert_eq!(eval("-2 ^ 2", 0.0, 0.0, 0.0), 4.0);
+    }
+
+    #[test]
+    fn reads_vars_and_calls() {
+        assert_eq!(eval("x + 2 * y - z", 1.0, 2.0, 3.0), 2.0);
+        assert_eq!(eval("r", 3.0, 0.0, 4.0), 5.0);
+        assert_eq!(eval("min(x, -y) + max(abs(z), 1)", 1.0, 2.0, -3.0), 1.0);
+        assert_eq!(eval("sqrt(x * x)", 4.0, 0.0, 0.0), 4.0);
+        assert_eq!(Parser::new(".5").parse(), Ok(Expr::Num(0.5)));
+    }
+
+    #[test]
+    fn rejects_bad_expressions() {
+        for src in ["1 +", "(x", "foo(x)", "sin", "min(x)", "sin(x, y)", "x y", "1..2", "x )"].iter() {
+            assert!(Parser::new(src).parse().is_err(), "{} parsed", src);
+        }
+    }
+
+    #[test]
+    fn parses_specs() {
+        assert_eq!(Field::parse(" 2.5"), Ok(Field::Const(2.5)));
+        assert_eq!(Field::parse("noise:0.1,0.5"), Ok(Field::Noise{scale: 0.1, amp: 0.5, seed: 0}));
+        assert_eq!(Field::parse("noise:0.1, 0.5, 7"), Ok(Field::Noise{scale: 0.1, amp: 0.5, seed: 7}));
+        assert_eq!(Field::parse("seed:2"), Ok(Field::SeedDistance{scale: 2.0, offset: 1.0}));
+        assert_eq!(Field::parse("expr:x"), Ok(Field::Expr(Expr::Var('x'))));
+        assert!(Field::parse("noise:0.1").is_err());
+        assert!(Field::parse("wave:1").is_err());
+        assert!(Field::parse("lots").is_err());
+    }
+}
@@ -2,9 +2,6 @@ extern crate nalgebra as na;
 extern crate kiss3d;
 extern crate time;
 extern crate glfw;
-extern crate image;
-use image::{ImageBuffer, Rgba};
-use std::fs::File;
 
 use shaded;
 use util;
@@ -14,14 +11,29 @@ use std::sync::mpsc::{Sender, Receiver};
 
 use std::rc::Rc;
 use std::cell::RefCell;
-use na::{Pnt3, Vec2};
+use na::{Pnt2, Pnt3, Vec3};
 use state::{State, DrawState};
+use color::{self, ColorScheme};
+use history::History;
+use sim::{Sim, Snapshot, Command, Counts};
+use overlay::Overlay;
+use pick::Picked;
+use tweak::TWEAKS;
+use diff::Diff;
+use real;
+use camscript::{self, CameraScript, Shot};
+use capture::{self, Resolution};
+use edges::EdgeStyle;
+use std::f32::consts::PI;
+use kiss3d::scene::SceneNode;
 use kiss3d::window::Window;
-use kiss3d::camera::ArcBall;
+use kiss3d::camera::{Camera, ArcBall};
 use kiss3d::resource::{Shader, ShaderAttribute, ShaderUniform, Material, Mesh, FramebufferManager};
 use kiss3d::builtin::UvsMaterial;
-use glfw::{Action, Key, WindowEvent};
+use glfw::{Action, Key, MouseButton, WindowEvent};
 use std::thread;
+use std::cmp;
+use std::mem;
 use std::path::Path;
 
 impl DrawState for Window {
@@ -30,100 +42,363 @@ impl DrawState for Window {
             let (a, b) = state.edge_pts(i);
             //let color = hsl((state.edges[i].age as f32 / 4.0) % 180.0 + 180.0, 1.0, 0.6);
             let color = state.edge_color(i, off);
-            self.draw_line(state.pos(a), state.pos(b), &color);
+            self.draw_line(&state.render_pos(a), &state.render_pos(b), &color);
         }
     }
 }
 
-fn shoot(window: &mut Window, outfile: String) {
-    let Vec2{x: mut width, y: mut height} = window.size();
-    width *= 2.0;
-    height *= 2.0;
-    let mut buf = Vec::new();
-    window.snap_rect(&mut buf, 0, 0, width as usize, height as usize);
+/// Draw the edges from `eye`, faded by depth as `style` asks. GL depth
+/// tests the lines, so unlike `aaline` the order doesn't matter here, and
+/// kiss3d lines all have the one width, so they can't thin with depth.
+pub fn draw_depth_edges(window: &mut Window, state: &State, eye: &Pnt3<f32>, off: f32, style: &EdgeStyle) {
+    for edge in state.depth_edges(eye, off, style).iter() {
+        window.draw_line(&edge.a, &edge.b, &edge.color);
+    }
+}
 
-    thread::spawn(move || {
-        vflip(&mut buf, (width * 3.0) as usize, height as usize);
-        let img = image::ImageBuffer::from_raw(width as u32, height as u32, buf).expect("Opening image for writing");
-        let mut fout = File::create(outfile.clone()).ok().expect("Open file");
-        image::ImageRgb8(img).save(&mut fout, image::PNG).ok().expect("Saving image");
-        println!("Wrote {}", outfile);
-    });
+/// An invisible copy of the surface that only fills the depth buffer, so
+/// edges behind it get hidden.
+pub fn add_occluder(window: &mut Window, state: &State) -> SceneNode {
+    let mesh = Rc::new(RefCell::new(Mesh::new(state.render_coords(), state.tris.clone(), None, Some(state.scheme_colors(ColorScheme::Age)), false)));
+    let material = Rc::new(RefCell::new(Box::new(shaded::ShaderMaterial::depth_only()) as Box<Material + 'static>));
+    let mut obj = window.add_mesh(mesh, na::one());
+    obj.enable_backface_culling(false);
+    obj.set_material(material);
+    obj
 }
 
 fn shoot_at(window: &mut Window, outfile: String, sender: Sender<(String, Box<Vec<u8>>, usize, usize)>) {
-    let Vec2{x: mut width, y: mut height} = window.size();
-    width *= 2.0;
-    height *= 2.0;
-    let mut buf = Vec::new();
-    window.snap_rect(&mut buf, 0, 0, width as usize, height as usize);
-    sender.send((outfile, Box::new(buf), width as usize, height as usize)).ok().expect("Sending to channel");
+    let (buf, width, height) = capture::snap(window);
+    sender.send((outfile, Box::new(buf), width, height)).ok().expect("Sending to channel");
+}
+
+// replace the mesh wholesale, since rewinding can shrink it
+fn update_mesh(obj: &mut SceneNode, snap: Snapshot) {
+    let Snapshot{coords, tris, colors, ..} = snap;
+    obj.modify_vertices(&mut move |current| {
+        current.truncate(0);
+        current.extend(coords.iter().cloned());
+    });
+    obj.modify_faces(&mut move |current| {
+        current.truncate(0);
+        current.extend(tris.iter().cloned());
+    });
+    obj.modify_uvs(&mut move |current| {
+        current.truncate(0);
+        current.extend(colors.iter().cloned());
+    });
 }
 
-fn vflip(vec: &mut [u8], width: usize, height: usize) {
-    for j in 0 .. height / 2 {
-        for i in 0 .. width {
-            vec.swap((height - j - 1) * width + i, j * width + i);
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Look {
+    Surface,
+    Wireframe,
+    Both,
+}
+
+impl Look {
+    fn next(&self) -> Look {
+        match *self {
+            Look::Surface => Look::Wireframe,
+            Look::Wireframe => Look::Both,
+            Look::Both => Look::Surface,
         }
     }
+
+    fn lines(&self) -> bool {
+        *self != Look::Surface
+    }
+}
+
+static KEYS: [&'static str; 21] = [
+    "p     pause / run",
+    ".     step one tick",
+    "- =   fewer / more ticks per frame",
+    "[ ]   rewind / forward",
+    "b     branch from here",
+    "x     reload",
+    "k     follow the camera script",
+    "o     auto-orbit",
+    "z     auto-zoom",
+    "f     frame the growth",
+    "l     surface / wireframe / both",
+    "c     next colour scheme",
+    "s     screenshot",
+    "r     record every frame",
+    "w     save the state",
+    "i     stats overlay",
+    "t     param panel: up/down pick, left/right adjust (shift x10)",
+    "shift-click   inspect a node, drag to pull it",
+    "ctrl-click    push out around a node",
+    "alt-click     pin / unpin a node",
+    "h     this help",
+];
+
+// the steps `-` and `=` go through; 0 is as fast as it'll go
+static PER_FRAME: [usize; 8] = [1, 2, 4, 8, 16, 32, 64, 0];
+
+fn frame_camera(bounds: (Pnt3<f32>, Pnt3<f32>)) -> ArcBall {
+    let (eye, at) = camscript::framing(bounds);
+    ArcBall::new(eye, at)
+}
+
+/// A camera set up for a scripted shot.
+pub fn shot_camera(shot: &Shot) -> ArcBall {
+    ArcBall::new_with_frustrum(shot.fov, 0.1, 1024.0, shot.eye, shot.at)
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Click {
+    Pick, // shift
+    Poke, // control
+    Pin, // alt
 }
 
-pub fn grow(window: &mut Window, max_time: i32, outfile: String, infile: Option<String>, hollow: bool, record: bool) {
-    let mut state = util::load_maybe(infile.clone(), 10);
+/// Track the cursor, and say which modifier-click this is, if any; plain
+/// clicks stay with the camera.
+fn click(value: &WindowEvent, cursor: &mut Pnt2<f32>) -> Option<Click> {
+    match *value {
+        WindowEvent::CursorPos(x, y) => {
+            *cursor = Pnt2::new(x as f32, y as f32);
+            None
+        },
+        WindowEvent::MouseButton(MouseButton::Button1, Action::Press, mods) => {
+            if mods.contains(glfw::Shift) {
+                Some(Click::Pick)
+            } else if mods.contains(glfw::Control) {
+                Some(Click::Poke)
+            } else if mods.contains(glfw::Alt) {
+                Some(Click::Pin)
+            } else {
+                None
+            }
+        },
+        _ => None,
+    }
+}
+
+fn draw_cross(window: &mut Window, at: &Pnt3<f32>, color: &Pnt3<f32>) {
+    let size = 0.05;
+    for off in [Vec3::new(size, 0.0, 0.0), Vec3::new(0.0, size, 0.0), Vec3::new(0.0, 0.0, size)].iter() {
+        window.draw_line(&(*at - *off), &(*at + *off), color);
+    }
+}
+
+fn draw_picked(window: &mut Window, picked: &Picked) {
+    let red = Pnt3::new(1.0, 0.0, 0.0);
+    if let Some(tri) = picked.tri {
+        let orange = Pnt3::new(1.0, 0.6, 0.0);
+        for k in 0..3 {
+            window.draw_line(&tri[k], &tri[(k + 1) % 3], &orange);
+        }
+    }
+    if let Some((a, b)) = picked.edge {
+        window.draw_line(&a, &b, &Pnt3::new(0.0, 0.3, 1.0));
+    }
+    for &(a, b) in picked.edges.iter() {
+        window.draw_line(&a, &b, &red);
+    }
+    draw_cross(window, &picked.at, &red);
+}
+
+fn hud(time: i32, counts: Counts, bounds: (Pnt3<f32>, Pnt3<f32>), ticks_per_sec: f32, fps: f32, recording: bool, scheme: ColorScheme) -> Vec<String> {
+    let size = bounds.1 - bounds.0;
+    vec![
+        format!("tick {}{}", time, if recording {"  recording"} else if counts.running {""} else {"  paused"}),
+        format!("{} nodes  {} edges  {} tris", counts.nodes, counts.edges, counts.tris),
+        format!("{:.1} ticks/s  {:.1} fps", if counts.running {ticks_per_sec} else {0.0}, fps),
+        format!("size {:.2} x {:.2} x {:.2}", size.x, size.y, size.z),
+        format!("mean strain {:.3}", counts.mean_strain),
+        format!("colour {}", scheme.name()),
+    ]
+}
+
+/// Run the growth in a window. The simulation ticks on its own thread (see
+/// `sim`), and each frame uploads whatever it has published since the last.
+/// `setup` is applied to every state loaded from scratch or the start file,
+/// and again on branching from a rewound snapshot, so an edited params file
+/// gets picked up there.
+pub fn grow(window: &mut Window, max_time: i32, outfile: String, infile: Option<String>, hollow: bool, record: bool, scheme: ColorScheme, history: History, overlay: &Overlay, script: Option<CameraScript>, resolution: Option<Resolution>, setup: Box<Fn(&mut State) + Send>) {
     let mut camera = ArcBall::new(Pnt3::new(0.0f32, 0.0, -7.0), Pnt3::new(0.0, 1.5, 0.0));
-    let start = time::get_time();
+    let mut look = if hollow {Look::Wireframe} else {Look::Surface};
+    let sim = Sim::spawn(max_time, outfile.clone(), infile, scheme, history, record, look.lines(), setup);
 
     let (sender, receiver): (Sender<(String, Box<Vec<u8>>, usize, usize)>, Receiver<_>) = mpsc::channel();
 
     thread::spawn(move || {
         loop {
-            let (outfile, mut buf, width, height) = match receiver.recv() {
+            let (outfile, buf, width, height) = match receiver.recv() {
                 Ok(x) => x,
                 Err(_) => {return},
             };
-            vflip(&mut *buf, width * 3, height);
-            let img = image::ImageBuffer::from_raw(width as u32, height as u32, *buf).expect("Create image");
-            let mut fout = File::create(outfile.clone()).ok().expect("Open file");
-            image::ImageRgb8(img).save(&mut fout, image::PNG).ok().expect("Save image");
-            println!("Wrote {}", outfile);
+            capture::save_png(*buf, width, height, outfile);
         }
     });
 
-    if state.time == 0 {
-        while state.tris.len() == 0 {
-            state.tick();
-        }
+    // the first snapshot comes once the seed has some triangles
+    let mut first = sim.take();
+    while first.is_none() {
+        thread::sleep_ms(5);
+        first = sim.take();
     }
-    let vertices = state.coords();
-    let indices = state.tris.clone();
-    let texture_idx = state.coord_colors(0.0);
-    let mesh  = Rc::new(RefCell::new(Mesh::new(vertices, indices, None, Some(texture_idx), false)));
+    let first = first.unwrap();
+    let mut shown = first.time;
+    let mut bounds = first.bounds;
+    let mut counts = first.counts;
+    let mut lines = first.lines;
+    let mesh  = Rc::new(RefCell::new(Mesh::new(first.coords, first.tris, None, Some(first.colors), false)));
     let material   = Rc::new(RefCell::new(Box::new(shaded::ShaderMaterial::default()) as Box<Material + 'static>));
     let mut obj = window.add_mesh(mesh, na::one());
     obj.set_color(0.0, 1.0, 0.0);
     obj.enable_backface_culling(false);
-    // obj.set_surface_rendering_activation(false);
+    obj.set_surface_rendering_activation(look != Look::Wireframe);
     obj.set_lines_width(15.0);
     obj.set_material(material);
 
-    let mut running = true;
+    let mut scheme = scheme;
     let mut recording = record;
+    let mut orbit = true;
+    let mut zoom = true;
+    // a camera script, when there is one, takes over from the drift
+    let mut follow = script.is_some();
+    let mut fov = PI / 4.0;
+    let mut help = false;
+    let mut stats = true;
+    let mut panel = false;
+    let mut tweaking = 0;
+    let mut tweaks = vec![];
+    let mut speed = PER_FRAME.len() - 1;
+    let mut frames = 0;
+    let mut since = time::precise_time_s();
+    let mut ticks_per_sec = 0.0;
+    let mut fps = 0.0;
+    let mut cursor = Pnt2::new(0.0, 0.0);
+    let mut picked: Option<Picked> = None;
+    let mut pins = vec![];
+    let mut drag = None;
+    let mut dragging = false;
+    // a pick sent, waiting to hear whether it grabbed anything
+    let mut grabbing = false;
+    let mut picks = 0;
     while window.render_with_camera(&mut camera) {
-        for event in window.events().iter() {
+        for mut event in window.events().iter() {
+            // while dragging a node the mouse is ours, not the camera's
+            if dragging {
+                match event.value {
+                    WindowEvent::CursorPos(x, y) => {
+                        event.inhibited = true;
+                        cursor = Pnt2::new(x as f32, y as f32);
+                        let (origin, dir) = camera.unproject(&cursor, &window.size());
+                        sim.send(Command::Drag(origin, dir));
+                        continue;
+                    },
+                    WindowEvent::MouseButton(MouseButton::Button1, Action::Release, _) => {
+                        event.inhibited = true;
+                        dragging = false;
+                        sim.send(Command::Release);
+                        continue;
+                    },
+                    _ => {}
+                }
+            }
+            if grabbing {
+                if let WindowEvent::MouseButton(MouseButton::Button1, Action::Release, _) = event.value {
+                    grabbing = false;
+                    sim.send(Command::Release);
+                }
+            }
+            if let Some(kind) = click(&event.value, &mut cursor) {
+                event.inhibited = true;
+                let (origin, dir) = camera.unproject(&cursor, &window.size());
+                sim.send(match kind {
+                    Click::Pick => {
+                        grabbing = true;
+                        picks += 1;
+                        Command::Pick(origin, dir)
+                    },
+                    Click::Poke => Command::Poke(origin, dir),
+                    Click::Pin => Command::Pin(origin, dir),
+                });
+                continue;
+            }
             match event.value {
-                WindowEvent::Key(code, _, Action::Press, _) => {
+                WindowEvent::Key(code, _, Action::Press, mods) => {
                     match code {
-                        Key::X => {
-                            state = util::load_maybe(infile.clone(), 10);
-                        },
+                        Key::X => sim.send(Command::Reload),
+                        Key::LeftBracket => sim.send(Command::Back),
+                        Key::RightBracket => sim.send(Command::Forward),
+                        Key::B => sim.send(Command::Branch),
                         Key::R => {
                             recording = !recording;
+                            sim.send(Command::Lockstep(recording));
                         },
                         Key::S => {
-                            shoot_at(window, format!("gen/{}-{:04}.png", outfile.clone(), state.time), sender.clone());
+                            let name = format!("gen/{}-{:04}.png", outfile.clone(), shown);
+                            match resolution {
+                                Some(res) => {
+                                    let shot = Shot{eye: camera.eye(), at: camera.at(), fov: fov};
+                                    let edges = if look.lines() {&lines[..]} else {&[][..]};
+                                    let buf = capture::capture(window, &shot, res.width, res.height, res.supersample, &mut |window: &mut Window| {
+                                        for &(a, b, color) in edges.iter() {
+                                            window.draw_line(&a, &b, &color);
+                                        }
+                                    });
+                                    sender.send((name, Box::new(buf), res.width, res.height)).ok().expect("Sending to channel");
+                                },
+                                None => shoot_at(window, name, sender.clone()),
+                            }
+                        },
+                        Key::P => sim.send(Command::Toggle),
+                        Key::Period => sim.send(Command::Step),
+                        Key::Minus | Key::Equal => {
+                            speed = if code == Key::Minus {
+                                if speed > 0 {speed - 1} else {0}
+                            } else {
+                                cmp::min(speed + 1, PER_FRAME.len() - 1)
+                            };
+                            sim.send(Command::PerFrame(PER_FRAME[speed]));
+                            match PER_FRAME[speed] {
+                                0 => println!("Ticking as fast as possible"),
+                                n => println!("Up to {} ticks per frame", n),
+                            }
+                        },
+                        Key::K => follow = !follow && script.is_some(),
+                        Key::O => orbit = !orbit,
+                        Key::Z => zoom = !zoom,
+                        Key::F => camera = frame_camera(bounds),
+                        Key::W => sim.send(Command::Save),
+                        Key::L => {
+                            look = look.next();
+                            obj.set_surface_rendering_activation(look != Look::Wireframe);
+                            sim.send(Command::Lines(look.lines()));
+                        },
+                        Key::C => {
+                            scheme = scheme.next();
+                            sim.send(Command::Scheme(scheme));
+                            println!("Colouring by {}", scheme.name());
+                        },
+                        Key::I => stats = !stats,
+                        Key::T => {
+                            panel = !panel;
+                            sim.send(Command::Panel(panel));
+                        },
+                        Key::Up | Key::Down if panel => {
+                            tweaking = (if code == Key::Up {tweaking + TWEAKS.len() - 1} else {tweaking + 1}) % TWEAKS.len();
+                            if !overlay.has_font() {
+                                println!("Tweaking {}", TWEAKS[tweaking].0);
+                            }
                         },
-                        Key::P => {
-                            running = !running;
+                        Key::Left | Key::Right if panel => {
+                            let steps = if mods.contains(glfw::Shift) {10.0} else {1.0};
+                            sim.send(Command::Tweak(tweaking, if code == Key::Left {-steps} else {steps}));
+                        },
+                        Key::H => {
+                            help = !help;
+                            if help && !overlay.has_font() {
+                                for line in KEYS.iter() {
+                                    println!("{}", line);
+                                }
+                            }
                         },
                         _ => {}
                     }
@@ -132,63 +407,108 @@ pub fn grow(window: &mut Window, max_time: i32, outfile: String, infile: Option<
             }
         }
 
-        if running && state.time < max_time {
+        if let Some(mut snap) = sim.take() {
+            // drift the camera along with the growth, by however many ticks
+            // went by (none, when rewinding)
+            let ticks = cmp::min(cmp::max(snap.time - shown, 0), 50) as f32;
+            shown = snap.time;
+            bounds = snap.bounds;
+            counts = snap.counts;
+            ticks_per_sec = snap.ticks_per_sec;
+            lines = mem::replace(&mut snap.lines, vec![]);
+            picked = snap.picked.take();
+            pins = mem::replace(&mut snap.pins, vec![]);
+            drag = snap.drag;
+            if grabbing && snap.picks == picks {
+                grabbing = false;
+                dragging = snap.grabbed;
+            }
+            tweaks = mem::replace(&mut snap.tweaks, vec![]);
+            update_mesh(&mut obj, snap);
             if recording {
-                shoot_at(window, format!("gen/{}-{:04}.png", outfile.clone(), state.time), sender.clone());
+                shoot_at(window, format!("gen/{}-{:04}.png", outfile.clone(), shown), sender.clone());
             }
 
-            state.tick();
-
-            // update stuff
-            let vertices = state.coords();
-            let indices = state.tris.clone();
-            let texture_idx = state.coord_colors(0.0);
-            obj.modify_vertices(&mut move |current| {
-                for i in 0..current.len() {
-                    current[i] = vertices[i];
-                }
-                let _: Vec<usize> = vertices[current.len()..].iter().map(|i| {current.push(*i); 0usize}).collect();
-            });
-            obj.modify_faces(&mut move |current| {
-                let _: Vec<usize> = indices[current.len()..].iter().map(|i| {current.push(*i); 0usize}).collect();
-            });
-            obj.modify_uvs(&mut move |current| {
-                for i in 0..current.len() {
-                    current[i] = texture_idx[i];
-                }
-                let _: Vec<usize> = texture_idx[current.len()..].iter().map(|i| {current.push(*i); 0usize}).collect();
-            });
-            //obj.modify_faces(&move |_| indices);
-            //obj.modify_uvs(&move |_| texture_idx);
-
             // move camera
-            let dist = camera.dist();
-            camera.set_dist(dist + 0.03);
-            let yaw = camera.yaw();
-            camera.set_yaw(yaw + 0.002);
-            let at = camera.at_mut();
-            at.y += 0.010;
+            match script {
+                Some(ref script) if follow => {
+                    let shot = script.shot(shown, Some(bounds));
+                    fov = shot.fov;
+                    camera = shot_camera(&shot);
+                },
+                _ => {
+                    if zoom {
+                        let dist = camera.dist();
+                        camera.set_dist(dist + 0.03 * ticks);
+                        let at = camera.at_mut();
+                        at.y += 0.010 * ticks;
+                    }
+                    if orbit {
+                        let yaw = camera.yaw();
+                        camera.set_yaw(yaw + 0.002 * ticks);
+                    }
+                },
+            }
+        }
+
+        if look.lines() {
+            for &(a, b, color) in lines.iter() {
+                window.draw_line(&a, &b, &color);
+            }
+        }
+        let mut corner = Pnt2::new(20.0, 20.0);
+        if stats {
+            let lines = hud(shown, counts, bounds, ticks_per_sec, fps, recording, scheme);
+            overlay.draw(window, &lines, corner, Pnt3::new(0.1, 0.1, 0.1));
+            corner.y += (lines.len() + 1) as f32 * overlay.line_height();
+        }
+        if help {
+            let keys: Vec<String> = KEYS.iter().map(|line| line.to_string()).collect();
+            overlay.draw(window, &keys, corner, Pnt3::new(0.1, 0.1, 0.1));
+            corner.y += (keys.len() + 1) as f32 * overlay.line_height();
+        }
+        if panel && tweaks.len() == TWEAKS.len() {
+            let rows: Vec<String> = TWEAKS.iter().zip(tweaks.iter()).enumerate().map(|(i, (&(param, _), value))|
+                format!("{} {} {}", if i == tweaking {">"} else {" "}, param, value)
+            ).collect();
+            overlay.draw(window, &rows, corner, Pnt3::new(0.0, 0.3, 0.0));
+            corner.y += (rows.len() + 1) as f32 * overlay.line_height();
         }
-        if state.time == max_time {
-            println!("Output");
-            util::write_out(&state, outfile.clone());
-            state.time += 1;
-        } else if state.time % 50 == 0 {
-            util::write_out(&state, outfile.clone() + ".tmp");
-            let diff = time::get_time() - start;
-            println!("At {} : {}", state.time, diff);
+        if let Some(ref picked) = picked {
+            draw_picked(window, picked);
+            overlay.draw(window, &picked.info, corner, Pnt3::new(0.6, 0.0, 0.0));
+        }
+        for pin in pins.iter() {
+            draw_cross(window, pin, &Pnt3::new(0.0, 0.2, 1.0));
+        }
+        if let Some((from, to)) = drag {
+            window.draw_line(&from, &to, &Pnt3::new(1.0, 0.5, 0.0));
+        }
+
+        frames += 1;
+        let now = time::precise_time_s();
+        if now - since >= 1.0 {
+            fps = (frames as f64 / (now - since)) as f32;
+            frames = 0;
+            since = now;
+            // without a font the console is the only place to see them
+            if stats && !overlay.has_font() {
+                println!("{}", hud(shown, counts, bounds, ticks_per_sec, fps, recording, scheme).join(", "));
+            }
         }
-        // window.draw_state(&mut state, 180.0);
     }
 }
 
-pub fn display(window: &mut Window, infile: String, hollow: bool) {
-    let mut state = util::load_state(infile);
+pub fn display(window: &mut Window, infile: String, hollow: bool, style: EdgeStyle, scheme: ColorScheme, overlay: &Overlay, script: Option<CameraScript>) {
+    let state = util::load_state(infile);
     let mut camera = ArcBall::new(Pnt3::new(0.0f32, 20.0, -50.0), na::orig());
+    let (min, max) = state.bounds();
+    let bounds = (real::to_render(&min), real::to_render(&max));
+    let mut frame = 0;
 
-    let vertices = state.coords();
+    let vertices = state.render_coords();
     let indices = state.tris.clone();
-    let texture_idx = state.coord_colors(0.0);
+    let texture_idx = state.scheme_colors(scheme);
     let mesh  = Rc::new(RefCell::new(Mesh::new(vertices, indices, None, Some(texture_idx), false)));
     let material   = Rc::new(RefCell::new(Box::new(shaded::ShaderMaterial::default()) as Box<Material + 'static>));
     if !hollow {
@@ -201,17 +521,93 @@ pub fn display(window: &mut Window, infile: String, hollow: bool) {
 
         //obj.set_texture_from_file(&Path::new("media/kitten.png"), "kitten");
         obj.set_material(material);
+    } else if style.hidden {
+        add_occluder(window, &state);
     }
 
     let mut off = 0.0;
+    let mut cursor = Pnt2::new(0.0, 0.0);
+    let mut picked: Option<Picked> = None;
     while window.render_with_camera(&mut camera) {
+        for mut event in window.events().iter() {
+            if click(&event.value, &mut cursor) == Some(Click::Pick) {
+                event.inhibited = true;
+                let (origin, dir) = camera.unproject(&cursor, &window.size());
+                picked = state.hit(&origin, &dir).map(|hit| state.picked(&hit));
+                if let Some(ref picked) = picked {
+                    for line in picked.info.iter() {
+                        println!("{}", line);
+                    }
+                }
+            }
+        }
         off = (off + 0.1) % 360.0;
         // material.inc_time();
         if hollow {
-            window.draw_state(&mut state, 180.0);
+            draw_depth_edges(window, &state, &camera.eye(), 180.0, &style);
+        }
+        if let Some(ref picked) = picked {
+            draw_picked(window, picked);
+            overlay.draw(window, &picked.info, Pnt2::new(20.0, 20.0), Pnt3::new(0.6, 0.0, 0.0));
+        } else if let Some(ref script) = script {
+            camera = shot_camera(&script.shot(frame, Some(bounds)));
+            frame += 1;
+        } else {
+            // hold still while inspecting
+            let yaw = camera.yaw();
+            camera.set_yaw(yaw + 0.004);
         }
-        let yaw = camera.yaw();
-        camera.set_yaw(yaw + 0.004);
     }
 }
 
+
+/// Show several saved states side by side in a grid, under one camera so
+/// they turn together. With `diff`, every state after the first is
+/// coloured by how far it is from the first, on a shared scale.
+pub fn compare(window: &mut Window, infiles: Vec<String>, scheme: ColorScheme, diff: Option<Diff>, overlay: &Overlay) {
+    let states: Vec<State> = infiles.iter().map(|name| util::load_state(name.clone())).collect();
+    let bounds: Vec<(Pnt3<f32>, Pnt3<f32>)> = states.iter().map(|state| {
+        let (min, max) = state.bounds();
+        (real::to_render(&min), real::to_render(&max))
+    }).collect();
+    let spacing = bounds.iter().fold(1.0f32, |most, &(min, max)| most.max(na::norm(&(max - min)))) * 1.1;
+    let columns = (states.len() as f32).sqrt().ceil() as usize;
+    let rows = (states.len() + columns - 1) / columns;
+
+    let colors: Vec<Vec<Pnt2<f32>>> = match diff {
+        None => states.iter().map(|state| state.scheme_colors(scheme)).collect(),
+        Some(diff) => {
+            let dists: Vec<Vec<f32>> = states.iter().map(|state|
+                state.distances(&states[0], diff).iter().map(|d| *d as f32).collect()
+            ).collect();
+            let max = dists.iter().skip(1).fold(0.0f32, |max, d| d.iter().fold(max, |max, v| max.max(*v)));
+            println!("Largest distance from {}: {}", infiles[0], max);
+            // the reference keeps its own colours
+            dists.iter().enumerate().map(|(k, d)| if k == 0 {states[0].scheme_colors(scheme)} else {color::heat_over(d, max)}).collect()
+        },
+    };
+
+    let mut labels = vec![];
+    for (k, state) in states.iter().enumerate() {
+        let (min, max) = bounds[k];
+        let center = min + (max - min) / 2.0;
+        // row 0 at the top, filling left to right
+        let slot = Vec3::new((k % columns) as f32 * spacing, -((k / columns) as f32) * spacing, 0.0);
+        let mesh = Rc::new(RefCell::new(Mesh::new(state.render_coords(), state.tris.clone(), None, Some(colors[k].clone()), false)));
+        let material = Rc::new(RefCell::new(Box::new(shaded::ShaderMaterial::default()) as Box<Material + 'static>));
+        let mut obj = window.add_mesh(mesh, na::one());
+        obj.enable_backface_culling(false);
+        obj.set_material(material);
+        obj.append_translation(&(slot - Vec3::new(center.x, center.y, center.z)));
+        labels.push(format!("{} ({}, {}): {} at tick {}, {} nodes", k + 1, k % columns + 1, k / columns + 1, infiles[k], state.time, state.pts.len()));
+    }
+    for label in labels.iter() {
+        println!("{}", label);
+    }
+
+    let middle = Pnt3::new((columns - 1) as f32 * spacing / 2.0, -((rows - 1) as f32) * spacing / 2.0, 0.0);
+    let mut camera = ArcBall::new(middle + Vec3::new(0.0, 0.0, -spacing * (cmp::max(columns, rows) as f32 + 0.5)), middle);
+    while window.render_with_camera(&mut camera) {
+        overlay.draw(window, &labels, Pnt2::new(20.0, 20.0), Pnt3::new(0.1, 0.1, 0.1));
+    }
+}
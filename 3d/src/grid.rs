@@ -0,0 +1,155 @@
+extern crate nalgebra as na;
+use na::Pnt3;
+use std::collections::HashMap;
+use real::Real;
+
+pub type Cell = (usize, usize, usize);
+
+/// Spatial bins over the node positions, shared by `push_away` and the
+/// collision pass.
+pub struct Grid {
+    bins: HashMap<Cell, Vec<usize>>,
+    min: Pnt3<Real>,
+    size: Real,
+}
+
+impl Grid {
+    pub fn new(coords: &[Pnt3<Real>], size: Real) -> Grid {
+        let mut minx = 0.0;
+        let mut miny = 0.0;
+        let mut minz = 0.0;
+        // TODO figure out: would using the max to reduce false-reads at the top-end of the
+        // help at all?
+        for &Pnt3{x, y, z} in coords {
+            if x < minx {minx = x;}
+            if y < miny {miny = y;}
+            if z < minz {minz = z;}
+        }
+        let mut grid = Grid {
+            bins: HashMap::new(),
+            min: Pnt3::new(minx, miny, minz),
+            size: size,
+        };
+        for (i, pos) in coords.iter().enumerate() {
+            let cell = grid.cell(pos);
+            grid.bins.entry(cell).or_insert(vec![]).push(i);
+        }
+        grid
+    }
+
+    #[inline]
+    fn scaled(&self, pos: &Pnt3<Real>) -> (Real, Real, Real) {
+        (
+            ((pos.x - self.min.x) / self.size).max(0.0),
+            ((pos.y - self.min.y) / self.size).max(0.0),
+            ((pos.z - self.min.z) / self.size).max(0.0),
+        )
+    }
+
+    #[inline]
+    pub fn cell(&self, pos: &Pnt3<Real>) -> Cell {
+        let (x, y, z) = self.scaled(pos);
+        (x.floor() as usize, y.floor() as usize, z.floor() as usize)
+    }
+
+    /// How many nodes share a bin with `pos`.
+    pub fn count_at(&self, pos: &Pnt3<Real>) -> usize {
+        self.bins.get(&self.cell(pos)).map_or(0, |arr| arr.len())
+    }
+
+    /// The bin holding `pos`, plus the bins across whichever faces, edges and
+    /// corner of it `pos` is closest to (up to 8 bins).
+    pub fn near(&self, pos: &Pnt3<Real>) -> Vec<&Vec<usize>> {
+        let (xp, yp, zp) = self.scaled(pos);
+        let (xn, yn, zn) = (xp as usize, yp as usize, zp as usize);
+        let nx = if xp.round() > xp {xn + 1} else if xn > 0 {xn - 1} else {xn};
+        let ny = if yp.round() > yp {yn + 1} else if yn > 0 {yn - 1} else {yn};
+        let nz = if zp.round() > zp {zn + 1} else if zn > 0 {zn - 1} else {zn};
+
+        let mut cells = vec![(xn, yn, zn)];
+        if nx != xn {
+            cells.push((nx, yn, zn));
+            if ny != yn {
+                cells.push((nx, ny, zn));
+                if nz != zn {
+                    cells.push((nx, ny, nz));
+                }
+            }
+            if nz != zn {
+                cells.push((nx, yn, nz));
+            }
+        }
+        if ny != yn {
+            cells.push((xn, ny, zn));
+            if nz != zn {
+                cells.push((xn, ny, nz));
+            }
+        }
+        if nz != zn {
+            cells.push((xn, yn, nz));
+        }
+        cells.iter().filter_map(|cell| self.bins.get(cell)).collect()
+    }
+
+    /// Every node index in the 3x3x3 block of bins around `pos`.
+    pub fn around(&self, pos: &Pnt3<Real>) -> Vec<usize> {
+        let (xn, yn, zn) = self.cell(pos);
+        let mut found = vec![];
+        for x in (if xn > 0 {xn - 1} else {0})..xn + 2 {
+            for y in (if yn > 0 {yn - 1} else {0})..yn + 2 {
+                for z in (if zn > 0 {zn - 1} else {0})..zn + 2 {
+                    if let Some(arr) = self.bins.get(&(x, y, z)) {
+                        found.extend(arr.iter().cloned());
+                    }
+                }
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Grid;
+    use na::Pnt3;
+
+    #[test]
+    fn bins_from_the_lowest_corner() {
+        let grid = Grid::new(&[Pnt3::new(-2.0, 0.5, 1.0), Pnt3::new(1.5, 3.0, 0.0)], 1.0);
+        assert_eq!(grid.cell(&Pnt3::new(-2.0, 0.5, 1.0)), (0, 0, 1));
+        assert_eq!(grid.cell(&Pnt3::new(1.5, 3.0, 0.0)), (3, 3, 0));
+        // below the lowest corner clamps to the first bin
+        assert_eq!(grid.cell(&Pnt3::new(-5.0, -1.0, -1.0)), (0, 0, 0));
+        assert_eq!(grid.count_at(&Pnt3::new(1.2, 3.9, 0.9)), 1);
+        assert_eq!(grid.count_at(&Pnt3::new(0.0, 0.0, 0.0)), 0);
+    }
+
+    #[test]
+    fn around_finds_the_neighbouring_bins() {
+        let coords = [
+            Pnt3::new(5.5, 5.5, 5.5),
+            Pnt3::new(6.5, 4.5, 5.5),
+            Pnt3::new(4.1, 6.9, 6.9),
+            Pnt3::new(7.5, 5.5, 5.5),
+            Pnt3::new(5.5, 5.5, 3.5),
+        ];
+        let grid = Grid::new(&coords, 1.0);
+        let mut found = grid.around(&coords[0]);
+        found.sort();
+        assert_eq!(found, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn near_leans_toward_the_closest_bins() {
+        let coords = [
+            Pnt3::new(5.9, 5.5, 5.5),
+            Pnt3::new(6.2, 5.5, 5.5),
+            Pnt3::new(4.8, 5.5, 5.5),
+        ];
+        let grid = Grid::new(&coords, 1.0);
+        let found: Vec<usize> = grid.near(&coords[0]).iter().flat_map(|bin| bin.iter().cloned()).collect();
+        assert!(found.contains(&0));
+        assert!(found.contains(&1));
+        assert!(!found.contains(&2));
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::VecDeque;
+use state::State;
+
+/// A bounded ring of full state snapshots for stepping the viewer back and
+/// forth through a run.
+pub struct History {
+    snaps: VecDeque<State>,
+    cap: usize,
+    every: i32,
+    // which snapshot is showing, while rewound
+    at: Option<usize>,
+}
+
+impl History {
+    pub fn new(cap: usize, every: i32) -> History {
+        History {
+            snaps: VecDeque::new(),
+            cap: cap.max(1),
+            every: every.max(1),
+            at: None,
+        }
+    }
+
+    pub fn rewound(&self) -> bool {
+        self.at.is_some()
+    }
+
+    fn push(&mut self, state: &State) {
+        if self.snaps.back().map_or(false, |last| last.time == state.time) {
+            return;
+        }
+        self.snaps.push_back(state.clone());
+        while self.snaps.len() > self.cap {
+            self.snaps.pop_front();
+        }
+    }
+
+    /// Snapshot the live state, if it's on an `every` tick.
+    pub fn record(&mut self, state: &State) {
+        if !self.rewound() && state.time % self.every == 0 {
+            self.push(state);
+        }
+    }
+
+    /// One snapshot further back. The first step back keeps `current` so
+    /// stepping forward again can get back to it.
+    pub fn back(&mut self, current: &State) -> Option<State> {
+        let at = match self.at {
+            Some(at) => at,
+            None => {
+                self.push(current);
+                self.snaps.len() - 1
+            },
+        };
+        if at == 0 {
+            self.at = Some(0);
+            return None;
+        }
+        self.at = Some(at - 1);
+        Some(self.snaps[at - 1].clone())
+    }
+
+    pub fn forward(&mut self) -> Option<State> {
+        let at = match self.at {
+            Some(at) if at + 1 < self.snaps.len() => at + 1,
+            _ => return None,
+        };
+        self.at = Some(at);
+        Some(self.snaps[at].clone())
+    }
+
+    /// Carry on from the snapshot showing, dropping everything after it.
+    pub fn branch(&mut self) {
+        if let Some(at) = self.at {
+            self.snaps.truncate(at + 1);
+        }
+        self.at = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+    use state::State;
+
+    fn at(time: i32) -> State {
+        let mut state = State::init();
+        state.time = time;
+        state
+    }
+
+    fn time(state: Option<State>) -> Option<i32> {
+        state.map(|state| state.time)
+    }
+
+    #[test]
+    fn steps_back_and_forward_through_the_ring() {
+        let mut history = History::new(3, 2);
+        for t in 0..9 {
+            history.record(&at(t));
+        }
+        // 0 to 8 every 2, then the live tick 9, keeping the last 3
+        assert_eq!(time(history.back(&at(9))), Some(8));
+        assert!(history.rewound());
+        assert_eq!(time(history.back(&at(9))), Some(6));
+        assert_eq!(time(history.back(&at(9))), None);
+        assert_eq!(time(history.forward()), Some(8));
+        assert_eq!(time(history.forward()), Some(9));
+        assert_eq!(time(history.forward()), None);
+    }
+
+    #[test]
+    fn holds_still_while_rewound() {
+        let mut history = History::new(10, 1);
+        history.record(&at(0));
+        history.record(&at(1));
+        assert_eq!(time(history.back(&at(1))), Some(0));
+        history.record(&at(2));
+        assert_eq!(time(history.forward()), Some(1));
+        assert_eq!(time(history.forward()), None);
+    }
+
+    #[test]
+    fn branching_drops_the_future() {
+        let mut history = History::new(10, 1);
+        for t in 0..5 {
+            history.record(&at(t));
+        }
+        assert_eq!(time(history.back(&at(4))), Some(3));
+        assert_eq!(time(history.back(&at(4))), Some(2));
+        history.branch();
+        assert!(!history.rewound());
+        assert_eq!(time(history.forward()), None);
+        history.record(&at(7));
+        assert_eq!(time(history.back(&at(8))), Some(7));
+        assert_eq!(time(history.back(&at(8))), Some(2));
+    }
+}
@@ -3,59 +3,104 @@ extern crate kiss3d;
 extern crate image;
 
 use util;
+use real;
+use camscript::{CameraScript, Shot};
 use aaline::DrawLine;
+use edges::{EdgeStyle, ZBuffer, to_pixel};
 
 use std::f32::consts::PI;
-use na::{Pnt3, PerspMat3, Iso3, Vec3, ToHomogeneous};
+use na::{Pnt3, PerspMat3, Iso3, Vec3};
 use state::{State, DrawState};
 use image::{ImageBuffer, Rgba};
 use std::fs::File;
 
 impl DrawState for ImageBuffer<Rgba<u8>, Vec<u8>> {
     fn draw_state(&mut self, state: &mut State, off: f32) {
-        let fov = PI / 4.0;
-        let znear = 0.1;
-        let zfar = 1024.0;
-        let eye = Pnt3::new(0.0f32, 0.0, -50.0);
-        let at: Pnt3<f32> = na::orig();
-        let projection = PerspMat3::new(800.0 / 600.0, fov, znear, zfar);
+        let shot = Shot{eye: Pnt3::new(0.0f32, 0.0, -50.0), at: na::orig(), fov: PI / 4.0};
+        draw_shot(self, state, off, &shot, EdgeStyle::plain(), 1.0);
+    }
+}
 
-        // let dist = na::norm(&(eye - at));
-        // let pitch = ((eye.y - at.y) / dist).acos();
-        // let yaw = (eye.z - at.z).atan2(eye.x - at.x);
+/// Draw the edges as seen from `shot`, filling the image, farthest first
+/// and depth cued as `style` asks. Lines are 2 pixels wide times `scale`,
+/// so a supersampled image still shrinks to 2 pixel lines.
+pub fn draw_shot(img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, state: &State, off: f32, shot: &Shot, style: EdgeStyle, scale: f32) {
+    let znear = 0.1;
+    let zfar = 1024.0;
+    let Shot{eye, at, fov} = *shot;
+    let (width, height) = (img.width() as f32, img.height() as f32);
+    let projection = PerspMat3::new(width / height, fov, znear, zfar);
 
-        // let px = at.x + dist * yaw.cos() * pitch.sin();
-        // let py = at.y + dist * pitch.cos();
-        // let pz = at.z + dist * yaw.sin() * pitch.sin();
+    // let dist = na::norm(&(eye - at));
+    // let pitch = ((eye.y - at.y) / dist).acos();
+    // let yaw = (eye.z - at.z).atan2(eye.x - at.x);
 
-        //let neye = Pnt3::new(px, py, pz);
+    // let px = at.x + dist * yaw.cos() * pitch.sin();
+    // let py = at.y + dist * pitch.cos();
+    // let pz = at.z + dist * yaw.sin() * pitch.sin();
 
-        let mut view_transform: Iso3<f32> = na::one();
-        // TODO do I need to call the eye function?
-        view_transform.look_at_z(&eye, &at, &Vec3::y());
+    //let neye = Pnt3::new(px, py, pz);
 
-        let proj_view = *projection.as_mat() * na::to_homogeneous(&na::inv(&view_transform).unwrap());
-        //let inv_proj_view = na::inv(&proj_view).unwrap();
+    let mut view_transform: Iso3<f32> = na::one();
+    // TODO do I need to call the eye function?
+    view_transform.look_at_z(&eye, &at, &Vec3::y());
 
-        for i in 0..state.num_edges() {
-            let (a, b) = state.edge_pts(i);
-            //let color = hsl((state.edges[i].age as f32 / 4.0) % 180.0 + 180.0, 1.0, 0.6);
-            let color = state.edge_color(i, off);
+    let proj_view = *projection.as_mat() * na::to_homogeneous(&na::inv(&view_transform).unwrap());
+    //let inv_proj_view = na::inv(&proj_view).unwrap();
 
-            let p1 = proj_view * state.pos(a).to_homogeneous();
-            let p2 = proj_view * state.pos(b).to_homogeneous();
-            self.draw_line(p1.x, p1.y, p2.x, p2.y, 2.0, &color);
-            // self.draw_line(state.pos(a), state.pos(b), &color);
+    let zbuf = if style.hidden {Some(ZBuffer::new(state, &proj_view, img.width() as usize, img.height() as usize))} else {None};
+    for edge in state.depth_edges(&eye, off, &style).iter() {
+        let (p1, p2) = match (to_pixel(&proj_view, &edge.a, width, height), to_pixel(&proj_view, &edge.b, width, height)) {
+            (Some(p1), Some(p2)) => (p1, p2),
+            // behind the eye
+            _ => continue,
+        };
+        let thickness = 2.0 * scale * (1.0 - 0.5 * style.cue * edge.far);
+        match zbuf {
+            Some(ref zbuf) => draw_visible(img, zbuf, p1, p2, thickness, &edge.color),
+            None => img.draw_line(p1.0, p1.1, p2.0, p2.1, thickness, &edge.color),
         }
     }
 }
 
-pub fn draw(infile: String, outfile: String) {
+// draw just the runs of the line in front of the surface, checking every
+// couple of pixels along it
+fn draw_visible(img: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, zbuf: &ZBuffer, p1: (f32, f32, f32), p2: (f32, f32, f32), thickness: f32, color: &Pnt3<f32>) {
+    let at = |t: f32| (p1.0 + (p2.0 - p1.0) * t, p1.1 + (p2.1 - p1.1) * t, p1.2 + (p2.2 - p1.2) * t);
+    let len = ((p2.0 - p1.0).powi(2) + (p2.1 - p1.1).powi(2)).sqrt();
+    let steps = (len / 2.0).ceil().max(1.0) as usize;
+    let mut run: Option<f32> = None;
+    for i in 0..steps + 1 {
+        let t = i as f32 / steps as f32;
+        let shown = i < steps && {
+            let (x, y, z) = at((i as f32 + 0.5) / steps as f32);
+            zbuf.visible(x, y, z)
+        };
+        match (run, shown) {
+            (None, true) => run = Some(t),
+            (Some(start), false) => {
+                let (a, b) = (at(start), at(t));
+                img.draw_line(a.0, a.1, b.0, b.1, thickness, color);
+                run = None;
+            },
+            _ => (),
+        }
+    }
+}
+
+pub fn draw(infile: String, outfile: String, script: Option<CameraScript>) {
     println!("Ready");
     let mut state = util::load_state(infile);
     println!("Loaded state");
     let mut img = image::ImageBuffer::new(200, 200);
-    img.draw_state(&mut state, 0.0);
+    match script {
+        Some(script) => {
+            let (min, max) = state.bounds();
+            let shot = script.shot(0, Some((real::to_render(&min), real::to_render(&max))));
+            draw_shot(&mut img, &state, 0.0, &shot, EdgeStyle::plain(), 1.0);
+        },
+        None => img.draw_state(&mut state, 0.0),
+    }
     /*
     for x in 0..100 {
         img.put_pixel(100 - x, x, image::Rgba([255, 0, 0, 255]));
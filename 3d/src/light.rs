@@ -0,0 +1,110 @@
+extern crate nalgebra as na;
+use na::{Pnt3, Vec3, Norm};
+use state::State;
+use params::Env;
+use grid::Grid;
+use real::Real;
+
+impl State {
+    /// Work out how much light (or nutrient) reaches each node, in [0, 1].
+    pub fn expose(&mut self) {
+        let exposure = match self.params.env_model {
+            Env::Off => return,
+            Env::Light => self.shade(),
+            Env::Nutrient => self.feed(),
+        };
+        for (node, val) in self.pts.iter_mut().zip(exposure.into_iter()) {
+            node.exposure = val;
+        }
+    }
+
+    // march from each node towards the light through the node bins, with
+    // every node in the way absorbing some of it
+    fn shade(&self) -> Vec<Real> {
+        let cell = self.params.env_cell;
+        let pos = self.coords();
+        let grid = Grid::new(&pos, cell);
+        let (min, max) = self.bounds();
+        let (dx, dy, dz) = self.params.light_dir;
+        let dir = Vec3::new(dx, dy, dz);
+        if dir.norm() < 1e-9 {
+            return vec![1.0; pos.len()];
+        }
+        let step = dir.normalize() * cell;
+        let inside = |p: &Pnt3<Real>| p.x >= min.x && p.y >= min.y && p.z >= min.z && p.x <= max.x && p.y <= max.y && p.z <= max.z;
+        pos.iter().map(|start| {
+            let mut blocked = 0;
+            // skip our own bin, it's mostly us and our neighbours
+            let mut at = *start + step;
+            while inside(&at) {
+                blocked += grid.count_at(&at);
+                at = at + step;
+            }
+            (-self.params.light_absorb * blocked as Real).exp()
+        }).collect()
+    }
+
+    // relax a nutrient field over a voxel box around the mesh, held at 1 on
+    // the box walls and eaten by the nodes in each voxel
+    fn feed(&self) -> Vec<Real> {
+        let cell = self.params.env_cell;
+        let pos = self.coords();
+        let (min, max) = self.bounds();
+        let origin = Pnt3::new(min.x - cell, min.y - cell, min.z - cell);
+        let dim = |lo: Real, hi: Real| ((hi - lo) / cell).ceil() as usize + 3;
+        let (nx, ny, nz) = (dim(min.x, max.x), dim(min.y, max.y), dim(min.z, max.z));
+        let index = |p: &Pnt3<Real>| {
+            let x = (((p.x - origin.x) / cell) as usize).min(nx - 1);
+            let y = (((p.y - origin.y) / cell) as usize).min(ny - 1);
+            let z = (((p.z - origin.z) / cell) as usize).min(nz - 1);
+            (z * ny + y) * nx + x
+        };
+        let mut eaters = vec![0.0; nx * ny * nz];
+        for p in pos.iter() {
+            eaters[index(p)] += 1.0;
+        }
+        let consume = self.params.nutrient_consume;
+        let mut conc = vec![1.0; nx * ny * nz];
+        for _ in 0..self.params.nutrient_iters {
+            let last = conc.clone();
+            for z in 1..nz - 1 {
+                for y in 1..ny - 1 {
+                    for x in 1..nx - 1 {
+                        let i = (z * ny + y) * nx + x;
+                        let around = last[i - 1] + last[i + 1] + last[i - nx] + last[i + nx] + last[i - nx * ny] + last[i + nx * ny];
+                        conc[i] = (around / 6.0 / (1.0 + consume * eaters[i])).max(0.0);
+                    }
+                }
+            }
+        }
+        pos.iter().map(|p| conc[index(p)]).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use state::State;
+    use params::Env;
+    use testing::node;
+
+    #[test]
+    fn shades_whatever_is_under_a_slab() {
+        let mut state = State::init();
+        state.pts.push(node(0.0, 0.0, 0.0));
+        state.pts.push(node(3.0, 0.0, 0.0));
+        for &x in [-0.25, 0.0, 0.25].iter() {
+            for &z in [-0.25, 0.0, 0.25].iter() {
+                state.pts.push(node(x, 5.0, z));
+            }
+        }
+        state.params.env_model = Env::Light;
+        state.params.env_cell = 1.0;
+        state.params.light_dir = (0.0, 1.0, 0.0);
+        state.expose();
+        let under = state.pts[0].exposure;
+        assert!((under - (-state.params.light_absorb * 9.0).exp()).abs() < 1e-6, "under the slab gets {}", under);
+        // off to the side, and the slab itself, see the light
+        assert_eq!(state.pts[1].exposure, 1.0);
+        assert_eq!(state.pts[2].exposure, 1.0);
+    }
+}
@@ -9,17 +9,60 @@ extern crate time;
 extern crate image;
 extern crate glfw;
 
+mod real;
 mod state;
+mod params;
+mod grid;
+mod collide;
+mod integrate;
+mod pbd;
+mod remesh;
+mod stats;
+mod stop;
+mod schedule;
+mod field;
+mod chem;
+mod color;
+mod history;
+mod sim;
+mod overlay;
+mod pick;
+mod poke;
+mod tweak;
+mod diff;
+mod camscript;
+mod edges;
+mod capture;
+mod light;
+mod aniso;
+mod regions;
 mod util;
 mod glcmd;
 mod imgcmd;
 mod aaline;
 mod drawcmd;
+mod rendercmd;
 mod shaded;
+#[cfg(test)]
+mod testing;
 
 use kiss3d::window::Window;
 use kiss3d::light::Light;
 use state::{State};
+use real::Real;
+use params::Scheme;
+use stats::{TickStats, StatsWriter};
+use stop::Stopper;
+use field::Field;
+use params::{Model, Env};
+use color::ColorScheme;
+use history::History;
+use overlay::Overlay;
+use diff::Diff;
+use camscript::CameraScript;
+use capture::Resolution;
+use rendercmd::Turntable;
+use edges::EdgeStyle;
 use na::Pnt3;
 
 extern crate docopt;
@@ -29,12 +72,15 @@ static USAGE: &'static str = "
 3d Growth and Awesomeness
 
 Usage:
-  grow show <maxtime> <outfile> [--start=<path>] [--hollow] [--record]
-  grow make <maxtime> <outfile> [--start=<path>]
-  grow draw <infile> <outfile>
+  grow show <maxtime> <outfile> [--start=<path>] [--hollow] [--record] [options]
+  grow make <maxtime> <outfile> [--start=<path>] [options]
+  grow draw <infile> <outfile> [--camera=<path>] [--size=<wxh>] [--supersample=<n>]
   grow once
   grow info <infile>
-  grow display <infile> [--hollow]
+  grow display <infile> [--hollow] [--cue=<amount>] [--hidden] [--color=<scheme>] [--font=<path>] [--camera=<path>]
+  grow compare <states>... [--color=<scheme>] [--diff=<mode>] [--font=<path>]
+  grow turntable <infile> [<outfile>] [--frames=<n>] [--degrees=<deg>] [--elevation=<deg>] [--camera=<path>] [--size=<wxh>] [--supersample=<n>] [--hollow] [--cue=<amount>] [--hidden] [--color=<scheme>] [--headless]
+  grow views <infile> [<outfile>] [--size=<wxh>] [--supersample=<n>] [--hollow] [--cue=<amount>] [--hidden] [--color=<scheme>] [--headless]
   grow (-h | --help)
   grow --version
 
@@ -42,36 +88,194 @@ Options:
   -h --help     Show this screen.
   --version     Show version.
   --start=<path>   The file to use as a base
+  --color=<scheme>   Colour by age, chem, exposure or region [default: age]
+  --history=<n>    Snapshots to keep for rewinding the viewer [default: 40]
+  --snap-every=<ticks>   Ticks between rewind snapshots [default: 25]
+  --collide     Resolve triangle-level self intersections each tick
+  --integrator=<name>   One of euler, verlet, rk4, pbd
+  --dt=<dt>     Timestep per tick
+  --remesh=<ticks>   Collapse and flip edges every so many ticks
+  --stats=<path>   Write per-tick energy stats (CSV for .csv, JSON lines otherwise)
+  --params=<path>  JSON file of params to override
+  --schedule=<path>   JSON file of param keyframes over time
+  --growth-field=<field>   Scale growth speed over space (see below)
+  --max-len-field=<field>  Scale max edge length over space
+  --stiffness-field=<field>   Scale edge stiffness over space
+  --chem=<model>   Run gray-scott or turing reaction-diffusion on the mesh
+  --env=<model>    Slow growth where light or nutrient doesn't reach
+  --aniso=<strength>   Favour growth along each node's direction, 0 to 1
+  --twist=<rad>    Turn the growth directions by this much per tick
+  --max-nodes=<n>  Stop once there are this many nodes
+  --max-size=<size>   Stop once the bounding box is this big
+  --min-kinetic=<e>   Stop once kinetic energy stays below this...
+  --still-ticks=<n>   ...for this many ticks
+  --no-split-ticks=<n>   Stop after this many ticks without a split
+  --budget=<secs>  Stop after this much wall clock time
+  --font=<path>    Font for on-screen text [default: kiss3d/media/font/Inconsolata.otf]
+  --camera=<path>  JSON camera script of keyframes and orbit/dolly moves. A turntable
+                   takes its frames from it instead of orbiting
+  --diff=<mode>    Colour compared states by distance from the first, per node or to the nearest surface
+  --size=<wxh>     Render screenshots at this size, like 3840x2160, instead of the window's.
+                   Drawn in tiles through the window, which must stay uncovered meanwhile
+  --supersample=<n>   With --size, render n times bigger each way and scale down [default: 1]
+  --frames=<n>     Frames in a turntable [default: 36]
+  --degrees=<deg>  How far a turntable turns [default: 360]
+  --elevation=<deg>   Turntable camera height, in degrees above level [default: 20]
+  --headless       Render edges in software, with no window
+  --cue=<amount>   Fade far edges toward the background in hollow views, 0 to 1 [default: 0.7].
+                   Software images also thin them; the window can only fade them
+  --hidden         Hide edges behind the surface in hollow views
+
+Fields are a number, expr:<expression in x y z r>, noise:<scale>,<amp>[,<seed>],
+seed:<scale>[,<offset>] (distance from the seed) or voxels:<path>.
 ";
 
-#[derive(Debug, RustcDecodable)]
+#[derive(Debug, RustcDecodable, Clone)]
 struct Args {
     arg_maxtime: Option<i32>,
     arg_outfile: Option<String>,
     arg_infile: Option<String>,
+    arg_states: Vec<String>,
     flag_start: Option<String>,
     flag_hollow: bool,
     flag_record: bool,
+    flag_color: String,
+    flag_history: usize,
+    flag_snap_every: i32,
+    flag_collide: bool,
+    flag_integrator: Option<String>,
+    flag_dt: Option<Real>,
+    flag_remesh: Option<i32>,
+    flag_stats: Option<String>,
+    flag_params: Option<String>,
+    flag_schedule: Option<String>,
+    flag_growth_field: Option<String>,
+    flag_max_len_field: Option<String>,
+    flag_stiffness_field: Option<String>,
+    flag_chem: Option<String>,
+    flag_env: Option<String>,
+    flag_aniso: Option<Real>,
+    flag_twist: Option<Real>,
+    flag_max_nodes: Option<usize>,
+    flag_max_size: Option<Real>,
+    flag_min_kinetic: Option<Real>,
+    flag_still_ticks: Option<i32>,
+    flag_no_split_ticks: Option<i32>,
+    flag_budget: Option<i64>,
+    flag_font: String,
+    flag_diff: Option<String>,
+    flag_camera: Option<String>,
+    flag_size: Option<String>,
+    flag_supersample: usize,
+    flag_frames: usize,
+    flag_degrees: f32,
+    flag_elevation: f32,
+    flag_headless: bool,
+    flag_cue: f32,
+    flag_hidden: bool,
     cmd_display: bool,
+    cmd_compare: bool,
     cmd_info: bool,
     cmd_make: bool,
     cmd_show: bool,
     cmd_once: bool,
     cmd_draw: bool,
+    cmd_turntable: bool,
+    cmd_views: bool,
 }
 
-fn make(max_time: i32, outfile: String, infile: Option<String>) {
-    let mut state = util::load_maybe(infile, 10);
+fn configure(state: &mut State, args: &Args) {
+    if let Some(ref path) = args.flag_params {
+        state.params = state.params.overlay(path.clone());
+    }
+    if let Some(ref path) = args.flag_schedule {
+        state.schedule = schedule::load(path.clone(), &state.params);
+        // later ticks only apply it when a value moves
+        state.params = schedule::apply(&state.schedule, &state.params, state.time);
+    }
+    let parse = |spec: &Option<String>| spec.as_ref().map(|spec| Field::parse(spec).unwrap_or_else(|e| panic!("{}", e)));
+    if args.flag_growth_field.is_some() {state.fields.growth = parse(&args.flag_growth_field);}
+    if args.flag_max_len_field.is_some() {state.fields.max_len = parse(&args.flag_max_len_field);}
+    if args.flag_stiffness_field.is_some() {state.fields.stiffness = parse(&args.flag_stiffness_field);}
+    if let Some(ref model) = args.flag_chem {
+        state.params.chem_model = match &model[..] {
+            "gray-scott" => Model::GrayScott,
+            "turing" => Model::Turing,
+            _ => panic!("Unknown chemistry model {}", model),
+        };
+    }
+    if let Some(ref model) = args.flag_env {
+        state.params.env_model = match &model[..] {
+            "light" => Env::Light,
+            "nutrient" => Env::Nutrient,
+            _ => panic!("Unknown environment model {}", model),
+        };
+    }
+    if let Some(aniso) = args.flag_aniso {
+        state.params.aniso = aniso;
+    }
+    if let Some(twist) = args.flag_twist {
+        state.params.aniso_twist = twist;
+    }
+    if args.flag_collide {
+        state.params.collide = true;
+    }
+    if let Some(ref name) = args.flag_integrator {
+        state.params.integrator = Scheme::from_name(name).expect(&format!("Unknown integrator {}", name));
+    }
+    if let Some(dt) = args.flag_dt {
+        state.params.dt = dt;
+    }
+    if let Some(every) = args.flag_remesh {
+        state.params.remesh_every = every;
+    }
+    {
+        let stop = &mut state.params.stop;
+        if args.flag_max_nodes.is_some() {stop.max_nodes = args.flag_max_nodes;}
+        if args.flag_max_size.is_some() {stop.max_size = args.flag_max_size;}
+        if args.flag_min_kinetic.is_some() {stop.min_kinetic = args.flag_min_kinetic;}
+        if let Some(ticks) = args.flag_still_ticks {stop.still_ticks = ticks;}
+        if args.flag_no_split_ticks.is_some() {stop.no_split_ticks = args.flag_no_split_ticks;}
+        if args.flag_budget.is_some() {stop.budget_secs = args.flag_budget;}
+    }
+}
+
+fn make(max_time: i32, outfile: String, infile: Option<String>, args: &Args) {
+    let mut state = util::load_maybe(infile, 10, &|state: &mut State| configure(state, args));
     let start = time::get_time();
+    let mut writer = args.flag_stats.clone().map(StatsWriter::new);
+    let mut last: Option<TickStats> = None;
+    let mut stopper = Stopper::new(state.params.stop.clone());
+    state.stopped = None;
 
     for i in state.time..max_time {
-        state.tick();
+        let splits = match writer {
+            Some(ref mut writer) => {
+                let stats = state.tick_stats();
+                writer.write(&stats);
+                if let Some(reason) = stats.blowup(last.as_ref(), stats::ENERGY_SPIKE) {
+                    println!("Blow-up at {}: {}", state.time, reason);
+                }
+                let splits = stats.splits;
+                last = Some(stats);
+                splits
+            },
+            None => state.tick(),
+        };
+        if let Some(reason) = stopper.check(&state, splits) {
+            println!("Stopping at {}: {}", state.time, reason);
+            state.stopped = Some(reason);
+            break;
+        }
         if i % 50 == 0 {
             util::write_out(&state, outfile.clone() + ".tmp");
             let diff = time::get_time() - start;
             println!("At {} : {}", i, diff);
         }
     }
+    if state.stopped.is_none() {
+        state.stopped = Some(format!("reached max time {}", max_time));
+    }
     println!("Output");
     util::write_out(&state, outfile.clone());
 }
@@ -90,6 +294,30 @@ fn just_once() {
     }
 }
 
+fn edge_style(args: &Args) -> EdgeStyle {
+    EdgeStyle{cue: args.flag_cue.max(0.0).min(1.0), hidden: args.flag_hidden}
+}
+
+/// `turntable` and `views`, through the window if there is one.
+fn render(window: Option<&mut Window>, args: &Args) {
+    let infile = args.arg_infile.clone().unwrap();
+    let scheme = ColorScheme::from_name(&args.flag_color).expect(&format!("Unknown colour scheme {}", args.flag_color));
+    let resolution = args.flag_size.as_ref().map(|size| Resolution::parse(size, args.flag_supersample));
+    if args.cmd_turntable {
+        let outfile = args.arg_outfile.clone().unwrap_or(format!("{}-turn", infile));
+        let turn = Turntable {
+            frames: args.flag_frames,
+            degrees: args.flag_degrees,
+            elevation: args.flag_elevation,
+            script: args.flag_camera.clone().map(CameraScript::load),
+        };
+        rendercmd::turntable(window, infile, outfile, turn, resolution, args.flag_hollow, edge_style(args), scheme);
+    } else {
+        let outfile = args.arg_outfile.clone().unwrap_or(format!("{}-views.png", infile));
+        rendercmd::views(window, infile, outfile, resolution, args.flag_hollow, edge_style(args), scheme);
+    }
+}
+
 fn main() {
     let args: Args = Docopt::new(USAGE)
         .and_then(|d| d.decode())
@@ -101,7 +329,7 @@ fn main() {
         return;
     }
     if args.cmd_make {
-        make(args.arg_maxtime.unwrap(), args.arg_outfile.unwrap(), args.flag_start);
+        make(args.arg_maxtime.unwrap(), args.arg_outfile.clone().unwrap(), args.flag_start.clone(), &args);
         return;
     }
     if args.cmd_info {
@@ -109,6 +337,10 @@ fn main() {
         info(args.arg_infile.unwrap());
         return;
     }
+    if (args.cmd_turntable || args.cmd_views) && args.flag_headless {
+        render(None, &args);
+        return;
+    }
 
     let mut window = Window::new("Grow");
     unsafe {
@@ -125,11 +357,26 @@ fn main() {
     window.set_light(Light::StickToCamera);
     // window.set_light(Light::Absolute(Pnt3::new(10.0, 1.0, 0.0)));
 
-    if args.cmd_draw {
-        drawcmd::draw(&mut window, args.arg_infile.unwrap(), args.arg_outfile.unwrap());
+    let script = args.flag_camera.clone().map(CameraScript::load);
+    let resolution = args.flag_size.as_ref().map(|size| Resolution::parse(size, args.flag_supersample));
+    let scheme = ColorScheme::from_name(&args.flag_color).expect(&format!("Unknown colour scheme {}", args.flag_color));
+    if args.cmd_turntable || args.cmd_views {
+        render(Some(&mut window), &args);
+    } else if args.cmd_draw {
+        drawcmd::draw(&mut window, args.arg_infile.unwrap(), args.arg_outfile.unwrap(), script, resolution);
+    } else if args.cmd_compare {
+        let diff = args.flag_diff.as_ref().map(|name| Diff::from_name(name).expect(&format!("Unknown diff mode {}", name)));
+        let overlay = Overlay::new(&args.flag_font);
+        glcmd::compare(&mut window, args.arg_states.clone(), scheme, diff, &overlay);
     } else if args.cmd_display {
-        glcmd::display(&mut window, args.arg_infile.unwrap(), args.flag_hollow);
+        let overlay = Overlay::new(&args.flag_font);
+        glcmd::display(&mut window, args.arg_infile.unwrap(), args.flag_hollow, edge_style(&args), scheme, &overlay, script);
     } else {
-        glcmd::grow(&mut window, args.arg_maxtime.unwrap(), args.arg_outfile.unwrap(), args.flag_start, args.flag_hollow, args.flag_record);
+        let history = History::new(args.flag_history, args.flag_snap_every);
+        // the simulation thread needs its own copy of the options
+        let options = args.clone();
+        let setup = Box::new(move |state: &mut State| configure(state, &options));
+        let overlay = Overlay::new(&args.flag_font);
+        glcmd::grow(&mut window, args.arg_maxtime.unwrap(), args.arg_outfile.clone().unwrap(), args.flag_start.clone(), args.flag_hollow, args.flag_record, scheme, history, &overlay, script, resolution, setup);
     }
 }
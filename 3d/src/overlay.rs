@@ -0,0 +1,46 @@
+extern crate nalgebra as na;
+extern crate kiss3d;
+use na::{Pnt2, Pnt3};
+use std::rc::Rc;
+use std::path::Path;
+use kiss3d::window::Window;
+use kiss3d::text::Font;
+
+const SIZE: i32 = 28;
+
+/// Text drawn over the scene. Without the font file, text goes to stdout
+/// instead, so a missing font never stops the viewer.
+pub struct Overlay {
+    font: Option<Rc<Font>>,
+}
+
+impl Overlay {
+    pub fn new(path: &str) -> Overlay {
+        let path = Path::new(path);
+        if !path.exists() {
+            println!("No font at {}, on-screen text goes to the console", path.display());
+            return Overlay{font: None};
+        }
+        Overlay{font: Some(Font::new(path, SIZE))}
+    }
+
+    pub fn has_font(&self) -> bool {
+        self.font.is_some()
+    }
+
+    pub fn line_height(&self) -> f32 {
+        SIZE as f32
+    }
+
+    /// Draw `lines` down from `corner`, in pixels from the top left.
+    pub fn draw(&self, window: &mut Window, lines: &[String], corner: Pnt2<f32>, color: Pnt3<f32>) {
+        let font = match self.font {
+            Some(ref font) => font,
+            None => return,
+        };
+        for (i, line) in lines.iter().enumerate() {
+            let at = Pnt2::new(corner.x, corner.y + i as f32 * SIZE as f32);
+            window.draw_text(line, &at, font, &color);
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use rustc_serialize::json::{self, Json};
+use std::fs::File;
+use std::io::Read;
+use real::Real;
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone, Copy, Debug)]
+pub enum Scheme {
+    Euler,
+    Verlet,
+    RK4,
+    PBD,
+}
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone, Copy, Debug)]
+pub enum Model {
+    Off,
+    GrayScott,
+    Turing,
+}
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone, Copy, Debug)]
+pub enum Env {
+    Off,
+    Light,
+    Nutrient,
+}
+
+/// Physical properties of one tissue type. All but `damp` scale the global
+/// params; `damp` replaces the global damping when set.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone, Copy, Debug)]
+pub struct Region {
+    pub stiffness: Real,
+    pub growth: Real,
+    pub damp: Option<Real>,
+    pub repulsion: Real,
+}
+
+impl Region {
+    pub fn plain() -> Region {
+        Region {
+            stiffness: 1.0,
+            growth: 1.0,
+            damp: None,
+            repulsion: 1.0,
+        }
+    }
+}
+
+/// Ways nodes get moved into a region after the seed.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone, Debug)]
+pub enum RegionRule {
+    Above{height: Real, region: u32},
+    Older{age: usize, region: u32},
+    // at `time`, everything within `radius` of a node picked by `seed`
+    Patch{time: i32, radius: Real, seed: u32, region: u32},
+}
+
+/// When `grow make` should give up before reaching its max time. Anything
+/// left as `None` is never checked.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone, Debug)]
+pub struct Stop {
+    pub max_nodes: Option<usize>,
+    pub max_size: Option<Real>,
+    pub min_kinetic: Option<Real>,
+    pub still_ticks: i32, // how long kinetic energy has to stay under `min_kinetic`
+    pub no_split_ticks: Option<i32>,
+    pub budget_secs: Option<i64>,
+}
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone, Debug)]
+pub struct Params {
+    pub integrator: Scheme,
+    pub dt: Real,
+    pub tolerance: Real,
+    pub damp: Real,
+    pub stick_k: Real,
+    pub avoid_k: Real,
+
+    pub max_len: Real,
+    pub too_crowded: usize, // neighbors
+    pub min_crowd: i32,
+    pub too_dead: i32,
+    pub dead_motion: Real,
+    pub close_dist: Real,
+    pub push_dist: Real,
+    pub grow_speed: Real,
+    pub max_speed: Real,
+    pub gravity: Real,
+    pub grav_top: Real,
+    pub grav_bottom: Real,
+
+    // triangle-level self collision
+    pub collide: bool,
+    pub collide_dist: Real,
+
+    // position based dynamics solver
+    pub pbd_iterations: usize,
+    pub pbd_stretch_compliance: Real,
+    pub pbd_contact_dist: Real,
+    pub pbd_contact_compliance: Real,
+    pub pbd_bend: bool,
+    pub pbd_bend_compliance: Real,
+
+    // remeshing, every `remesh_every` ticks (0 turns it off)
+    pub remesh_every: i32,
+    pub collapse_ratio: Real, // of the rest length
+    pub flip_angle: Real, // beyond PI before flipping
+
+    pub stop: Stop,
+
+    // reaction-diffusion on the mesh
+    pub chem_model: Model,
+    pub chem_steps: usize,
+    pub chem_dt: Real,
+    pub chem_du: Real,
+    pub chem_dv: Real,
+    pub chem_feed: Real, // gray-scott feed, or turing production
+    pub chem_kill: Real, // gray-scott kill, or turing decay
+    pub chem_growth: Real, // how much the morphogen speeds up growth
+
+    // competition for light or nutrients
+    pub env_model: Env,
+    pub env_every: i32,
+    pub env_cell: Real,
+    pub env_min: Real, // growth rate with no exposure at all
+    pub light_dir: (Real, Real, Real), // towards the light
+    pub light_absorb: Real, // per node in the way
+    pub nutrient_consume: Real,
+    pub nutrient_iters: usize,
+
+    // anisotropic growth along each node's `dir`
+    pub aniso: Real, // 0 grows every edge alike, 1 only grows aligned edges
+    pub aniso_dir: (Real, Real, Real), // starting direction, or zero for the seed tangent
+    pub aniso_smooth: Real, // how much neighbours pull on the direction each tick
+    pub aniso_twist: Real, // radians per tick to turn about the surface normal
+
+    // tissue types, indexed by the region id on nodes and edges
+    pub regions: Vec<Region>,
+    pub region_rules: Vec<RegionRule>,
+    pub seed_region: u32,
+
+    // poking at it from the viewer
+    pub drag_k: Real, // spring from a dragged node to the cursor
+    pub impulse: Real, // speed given to nodes at the centre of a poke
+    pub impulse_radius: Real,
+}
+
+impl Params {
+    pub fn default() -> Params {
+        Params {
+            integrator: Scheme::Euler,
+            dt: 1.0,
+            tolerance: 0.001,
+            damp: 0.75,
+            stick_k: 0.09,
+            avoid_k: 0.02,
+
+            max_len: 0.5,
+            too_crowded: 34,
+            min_crowd: 5,
+            too_dead: 100,
+            dead_motion: 0.0001,
+            close_dist: 2.0,
+            push_dist: 0.8,
+            grow_speed: 0.01,
+            max_speed: 0.02,
+            gravity: 0.01,
+            grav_top: 10.0,
+            grav_bottom: 7.0,
+
+            collide: false,
+            collide_dist: 0.1,
+
+            pbd_iterations: 10,
+            pbd_stretch_compliance: 0.0,
+            pbd_contact_dist: 0.3,
+            pbd_contact_compliance: 0.001,
+            pbd_bend: false,
+            pbd_bend_compliance: 1.0,
+
+            remesh_every: 0,
+            collapse_ratio: 0.3,
+            flip_angle: 0.1,
+
+            stop: Stop {
+                max_nodes: None,
+                max_size: None,
+                min_kinetic: None,
+                still_ticks: 100,
+                no_split_ticks: None,
+                budget_secs: None,
+            },
+
+            chem_model: Model::Off,
+            chem_steps: 4,
+            chem_dt: 1.0,
+            chem_du: 0.16,
+            chem_dv: 0.08,
+            chem_feed: 0.035,
+            chem_kill: 0.065,
+            chem_growth: 1.0,
+
+            env_model: Env::Off,
+            env_every: 10,
+            env_cell: 0.5,
+            env_min: 0.1,
+            light_dir: (0.0, 1.0, 0.0),
+            light_absorb: 0.2,
+            nutrient_consume: 0.05,
+            nutrient_iters: 20,
+
+            aniso: 0.0,
+            aniso_dir: (0.0, 0.0, 0.0),
+            aniso_smooth: 0.2,
+            aniso_twist: 0.0,
+
+            regions: vec![Region::plain()],
+            region_rules: vec![],
+            seed_region: 0,
+
+            drag_k: 0.05,
+            impulse: 0.5,
+            impulse_radius: 1.0,
+        }
+    }
+}
+
+// overlay whatever keys `top` has onto `base`, recursing into objects
+fn merge(base: &mut Json, top: Json) {
+    match (base, top) {
+        (&mut Json::Object(ref mut base), Json::Object(top)) => {
+            for (key, value) in top.into_iter() {
+                match base.get_mut(&key) {
+                    Some(existing) => {
+                        merge(existing, value);
+                        continue;
+                    },
+                    None => {}
+                }
+                base.insert(key, value);
+            }
+        },
+        (base, top) => *base = top,
+    }
+}
+
+impl Params {
+    /// Read a JSON params file. Only the keys present in the file are
+    /// changed, everything else is kept from `self`.
+    pub fn overlay(&self, path: String) -> Params {
+        let mut text = String::new();
+        File::open(path.clone()).ok().expect(&format!("Can't read params file: {}", path))
+            .read_to_string(&mut text).ok().expect(&format!("Can't read params file: {}", path));
+        let top = Json::from_str(&text).ok().expect(&format!("Invalid JSON in {}", path));
+        let mut base = self.to_json();
+        merge(&mut base, top);
+        json::decode(&base.to_string()).ok().expect(&format!("Unable to load params - is the format right? {}", path))
+    }
+
+    pub fn to_json(&self) -> Json {
+        Json::from_str(&json::encode(self).ok().expect("Encoding params")).ok().expect("Encoding params")
+    }
+}
@@ -0,0 +1,162 @@
+extern crate nalgebra as na;
+use na::{Pnt3, Vec3, Norm, FloatPnt};
+use std::collections::HashMap;
+use state::State;
+use grid::Grid;
+use integrate::Integrator;
+use real::Real;
+
+/// Extended position based dynamics: edges, contacts and (optionally)
+/// bending are solved as constraints on predicted positions instead of
+/// springs, so edge lengths stay pinned to their rest `len`.
+pub struct PBD;
+
+struct Bend {
+    a: usize,
+    b: usize,
+    // opposite corners of the two triangles sharing a-b
+    c: usize,
+    d: usize,
+}
+
+// distance between c and d if the two triangles on a-b were unfolded flat
+fn flat_dist(a: Pnt3<Real>, b: Pnt3<Real>, c: Pnt3<Real>, d: Pnt3<Real>) -> Real {
+    let axis = b - a;
+    let alen = axis.norm();
+    if alen < 1e-9 {
+        return c.dist(&d);
+    }
+    let axis = axis / alen;
+    let tc = na::dot(&(c - a), &axis);
+    let td = na::dot(&(d - a), &axis);
+    let hc = ((c - a) - axis * tc).norm();
+    let hd = ((d - a) - axis * td).norm();
+    ((tc - td).powi(2) + (hc + hd).powi(2)).sqrt()
+}
+
+fn bends(state: &State) -> Vec<Bend> {
+    let mut opposite: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+    for tri in state.tris.iter() {
+        let corners = [tri.x as usize, tri.y as usize, tri.z as usize];
+        for k in 0..3 {
+            let (a, b, c) = (corners[k], corners[(k + 1) % 3], corners[(k + 2) % 3]);
+            let key = if a < b {(a, b)} else {(b, a)};
+            opposite.entry(key).or_insert(vec![]).push(c);
+        }
+    }
+    let mut found = vec![];
+    for (&(a, b), corners) in opposite.iter() {
+        if corners.len() == 2 && corners[0] != corners[1] {
+            found.push(Bend{a: a, b: b, c: corners[0], d: corners[1]});
+        }
+    }
+    found
+}
+
+// one XPBD distance projection; returns the lambda increment
+fn project(pos: &mut [Pnt3<Real>], i: usize, j: usize, rest: Real, lambda: Real, alpha: Real) -> Real {
+    let delta: Vec3<Real> = pos[i] - pos[j];
+    let dist = delta.norm();
+    if dist < 1e-9 {
+        return 0.0;
+    }
+    let n = delta / dist;
+    let c = dist - rest;
+    let dlambda = (-c - alpha * lambda) / (2.0 + alpha);
+    pos[i] = pos[i] + n * dlambda;
+    pos[j] = pos[j] - n * dlambda;
+    dlambda
+}
+
+impl Integrator for PBD {
+    fn step(&self, state: &mut State, dt: Real) {
+        let start = state.coords();
+        let num = start.len();
+
+        // the contacts to keep apart this step
+        let grid = Grid::new(&start, state.params.close_dist * 2.0);
+        let mut contacts = vec![];
+        for i in 0..num {
+            for arr in grid.near(&start[i]) {
+                for &j in arr {
+                    if j <= i || state.pts[i].left == j || state.pts[i].right == j {
+                        continue;
+                    }
+                    if start[i].dist(&start[j]) < state.params.pbd_contact_dist {
+                        contacts.push((i, j));
+                    }
+                }
+            }
+        }
+
+        // predict with the body forces only
+        let mut accel: Vec<Vec3<Real>> = vec![na::zero(); num];
+        state.gravitate(&start, &mut accel, 1.0);
+        let mut vel: Vec<Vec3<Real>> = state.pts.iter().zip(accel.iter()).map(|(n, a)| n.vel + *a * dt).collect();
+        state.constrain(&mut vel);
+        let mut pos: Vec<Pnt3<Real>> = start.iter().zip(vel.iter()).map(|(p, v)| *p + *v * dt).collect();
+
+        let bends = if state.params.pbd_bend {bends(state)} else {vec![]};
+        let rests: Vec<Real> = bends.iter().map(|b| flat_dist(start[b.a], start[b.b], start[b.c], start[b.d])).collect();
+        let stretch_alpha = state.params.pbd_stretch_compliance / (dt * dt);
+        let contact_alpha = state.params.pbd_contact_compliance / (dt * dt);
+        let bend_alpha = state.params.pbd_bend_compliance / (dt * dt);
+        let mut stretch_lambda = vec![0.0; state.edges.len()];
+        let mut bend_lambda = vec![0.0; bends.len()];
+
+        for _ in 0..state.params.pbd_iterations {
+            for (e, edge) in state.edges.iter().enumerate() {
+                stretch_lambda[e] += project(&mut pos, edge.a, edge.b, edge.len, stretch_lambda[e], stretch_alpha);
+            }
+            for &(i, j) in contacts.iter() {
+                if pos[i].dist(&pos[j]) < state.params.pbd_contact_dist {
+                    project(&mut pos, i, j, state.params.pbd_contact_dist, 0.0, contact_alpha);
+                }
+            }
+            for (k, bend) in bends.iter().enumerate() {
+                bend_lambda[k] += project(&mut pos, bend.c, bend.d, rests[k], bend_lambda[k], bend_alpha);
+            }
+            for i in 0..num.min(10) {
+                pos[i].y = start[i].y;
+            }
+            for i in 0..num {
+                if state.pts[i].pinned {
+                    pos[i] = start[i];
+                }
+            }
+        }
+
+        for i in 0..num {
+            vel[i] = (pos[i] - start[i]) / dt * state.damp(i).powf(dt);
+        }
+        state.constrain(&mut vel);
+        for i in 0..num {
+            state.pts[i].pos = pos[i];
+            state.pts[i].vel = vel[i];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use na::FloatPnt;
+    use integrate::Integrator;
+    use super::PBD;
+    use testing::{node, edge, anchored};
+
+    #[test]
+    fn pulls_an_edge_to_its_rest_length() {
+        let mut state = anchored(vec![node(0.0, 0.0, 0.0), node(2.0, 0.0, 0.0)]);
+        state.edges.push(edge(10, 11, 1.0));
+        // no carrying on past it on the next step
+        state.params.damp = 0.0;
+        state.params.pbd_stretch_compliance = 0.0;
+        for _ in 0..5 {
+            PBD.step(&mut state, 1.0);
+            let len = state.pts[10].pos.dist(&state.pts[11].pos);
+            assert!((len - 1.0).abs() < 1e-4, "edge is {} long", len);
+        }
+        // and it's the ends that met in the middle
+        assert!((state.pts[10].pos.x - 0.5).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,203 @@
+extern crate nalgebra as na;
+use na::{Pnt3, Vec3, Norm};
+use state::State;
+use real::{self, Real};
+
+// distance along the ray to triangle abc (Moller-Trumbore), if it hits, and
+// the hit's weights on b and c
+fn ray_tri(origin: Pnt3<Real>, dir: Vec3<Real>, a: Pnt3<Real>, b: Pnt3<Real>, c: Pnt3<Real>) -> Option<(Real, Real, Real)> {
+    let e1 = b - a;
+    let e2 = c - a;
+    let p = na::cross(&dir, &e2);
+    let det = na::dot(&e1, &p);
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let t0 = origin - a;
+    let u = na::dot(&t0, &p) / det;
+    if u < 0.0 || u > 1.0 {
+        return None;
+    }
+    let q = na::cross(&t0, &e1);
+    let v = na::dot(&dir, &q) / det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = na::dot(&e2, &q) / det;
+    if t > 0.0 {Some((t, u, v))} else {None}
+}
+
+/// What a ray from the camera found: the node to show and drag, and if it
+/// hit the surface, the triangle and the edge of it nearest the hit.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Hit {
+    pub node: usize,
+    pub tri: Option<usize>,
+    pub edge: Option<usize>,
+}
+
+/// A picked node, ready to highlight and show, with the triangle and edge
+/// hit if there were any.
+pub struct Picked {
+    pub at: Pnt3<f32>,
+    pub edges: Vec<(Pnt3<f32>, Pnt3<f32>)>,
+    pub tri: Option<[Pnt3<f32>; 3]>,
+    pub edge: Option<(Pnt3<f32>, Pnt3<f32>)>,
+    pub info: Vec<String>,
+}
+
+impl State {
+    /// The node under a ray cast from the camera.
+    pub fn pick(&self, origin: &Pnt3<f32>, dir: &Vec3<f32>) -> Option<usize> {
+        self.hit(origin, dir).map(|hit| hit.node)
+    }
+
+    /// What's under a ray cast from the camera: the nearest triangle hit,
+    /// its corner closest to the hit and the side of it closest too, or
+    /// failing that (say, a dangling node) the node nearest the ray within
+    /// `max_len`.
+    pub fn hit(&self, origin: &Pnt3<f32>, dir: &Vec3<f32>) -> Option<Hit> {
+        let origin = real::from_render(origin);
+        let dir = real::dir_from_render(dir).normalize();
+
+        let mut best: Option<(Real, usize, usize, (usize, usize))> = None;
+        for (n, tri) in self.tris.iter().enumerate() {
+            let corners = [tri.x as usize, tri.y as usize, tri.z as usize];
+            let (a, b, c) = (self.pts[corners[0]].pos, self.pts[corners[1]].pos, self.pts[corners[2]].pos);
+            let (t, u, v) = match ray_tri(origin, dir, a, b, c) {
+                Some(hit) => hit,
+                None => continue,
+            };
+            if best.map_or(false, |(bt, _, _, _)| bt <= t) {
+                continue;
+            }
+            let hit = origin + dir * t;
+            let mut near = corners[0];
+            for &k in corners.iter() {
+                if na::norm(&(self.pts[k].pos - hit)) < na::norm(&(self.pts[near].pos - hit)) {
+                    near = k;
+                }
+            }
+            // the corner with the least weight is the one across from the
+            // nearest side
+            let w = 1.0 - u - v;
+            let side = if w <= u && w <= v {
+                (corners[1], corners[2])
+            } else if u <= v {
+                (corners[2], corners[0])
+            } else {
+                (corners[0], corners[1])
+            };
+            best = Some((t, n, near, side));
+        }
+        if let Some((_, n, i, (p, q))) = best {
+            let edge = self.edges.iter().position(|e| (e.a == p && e.b == q) || (e.a == q && e.b == p));
+            return Some(Hit{node: i, tri: Some(n), edge: edge});
+        }
+
+        let mut nearest: Option<(Real, usize)> = None;
+        for (i, node) in self.pts.iter().enumerate() {
+            let to = node.pos - origin;
+            let along = na::dot(&to, &dir);
+            if along <= 0.0 {
+                continue;
+            }
+            let off = na::norm(&(to - dir * along));
+            if off < self.params.max_len && nearest.map_or(true, |(d, _)| off < d) {
+                nearest = Some((off, i));
+            }
+        }
+        nearest.map(|(_, i)| Hit{node: i, tri: None, edge: None})
+    }
+
+    /// Edges with node `i` at either end.
+    pub fn node_edges(&self, i: usize) -> Vec<usize> {
+        (0..self.edges.len()).filter(|&e| self.edges[e].a == i || self.edges[e].b == i).collect()
+    }
+
+    // the hit's triangle and edge, if they're still there since the pick
+    fn hit_parts(&self, hit: &Hit) -> (Option<usize>, Option<usize>) {
+        (hit.tri.and_then(|t| if t < self.tris.len() {Some(t)} else {None}),
+         hit.edge.and_then(|e| if e < self.edges.len() {Some(e)} else {None}))
+    }
+
+    pub fn picked(&self, hit: &Hit) -> Picked {
+        let (tri, edge) = self.hit_parts(hit);
+        Picked {
+            at: self.render_pos(hit.node),
+            edges: self.node_edges(hit.node).iter().map(|&e| {
+                let (a, b) = self.edge_pts(e);
+                (self.render_pos(a), self.render_pos(b))
+            }).collect(),
+            tri: tri.map(|t| {
+                let Pnt3{x, y, z} = self.tris[t];
+                [self.render_pos(x as usize), self.render_pos(y as usize), self.render_pos(z as usize)]
+            }),
+            edge: edge.map(|e| {
+                let (a, b) = self.edge_pts(e);
+                (self.render_pos(a), self.render_pos(b))
+            }),
+            info: self.describe(hit),
+        }
+    }
+
+    /// Everything about the picked node and its edges, and the triangle and
+    /// edge hit, a line each.
+    pub fn describe(&self, hit: &Hit) -> Vec<String> {
+        let i = hit.node;
+        let node = &self.pts[i];
+        let mut lines = vec![
+            format!("node {} of {}", i, self.pts.len()),
+            format!("pos ({:.3}, {:.3}, {:.3})", node.pos.x, node.pos.y, node.pos.z),
+            format!("vel ({:.4}, {:.4}, {:.4})", node.vel.x, node.vel.y, node.vel.z),
+            format!("nclose {}  siblings {}", node.nclose, node.siblings),
+            format!("age {}  dead {}", node.age, node.dead),
+            format!("left {}  right {}  trunk {}", node.left, node.right, node.trunk),
+        ];
+        for e in self.node_edges(i) {
+            let edge = &self.edges[e];
+            let other = if edge.a == i {edge.b} else {edge.a};
+            lines.push(format!("edge {} to {}: len {:.3} curlen {:.3}", e, other, edge.len, edge.curlen));
+        }
+        let (tri, edge) = self.hit_parts(hit);
+        if let Some(t) = tri {
+            let Pnt3{x, y, z} = self.tris[t];
+            lines.push(format!("hit tri {} of {}: nodes {} {} {}", t, self.tris.len(), x, y, z));
+        }
+        if let Some(e) = edge {
+            let edge = &self.edges[e];
+            lines.push(format!("nearest edge {}: {} to {}, len {:.3} curlen {:.3}", e, edge.a, edge.b, edge.len, edge.curlen));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Hit;
+    use na::{Pnt3, Vec3};
+    use testing::{node, edge, anchored};
+
+    #[test]
+    fn hits_a_known_triangle() {
+        let mut state = anchored(vec![node(0.0, 0.0, 0.0), node(2.0, 0.0, 0.0), node(0.0, 2.0, 0.0)]);
+        state.edges.push(edge(10, 11, 1.0));
+        state.edges.push(edge(11, 12, 1.0));
+        state.edges.push(edge(12, 10, 1.0));
+        state.tris.push(Pnt3::new(10, 11, 12));
+        let down = Vec3::new(0.0, 0.0, -1.0);
+        // nearest the first corner, and the side along x = 0
+        assert_eq!(state.hit(&Pnt3::new(0.3, 0.8, 5.0), &down), Some(Hit{node: 10, tri: Some(0), edge: Some(2)}));
+        // from underneath, nearest the second corner and the long side
+        assert_eq!(state.hit(&Pnt3::new(1.5, 0.4, -5.0), &-down), Some(Hit{node: 11, tri: Some(0), edge: Some(1)}));
+        // clear of the triangle, and of every node
+        assert_eq!(state.hit(&Pnt3::new(5.0, 5.0, 5.0), &down), None);
+    }
+
+    #[test]
+    fn falls_back_to_the_nearest_node() {
+        let state = anchored(vec![]);
+        let hit = state.hit(&Pnt3::new(100.1, 100.0, 5.0), &Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(hit, Some(Hit{node: 0, tri: None, edge: None}));
+    }
+}
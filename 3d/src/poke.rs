@@ -0,0 +1,89 @@
+extern crate nalgebra as na;
+use na::{Pnt3, Vec3, Norm, FloatPnt};
+use state::State;
+use real::Real;
+
+impl State {
+    /// Pull node `i` toward `target` on a spring of `drag_k`, as a kick to
+    /// its velocity, so every integrator sees it on the next tick.
+    pub fn pull(&mut self, i: usize, target: &Pnt3<Real>) {
+        if i >= self.pts.len() || self.pts[i].pinned {
+            return;
+        }
+        let toward: Vec3<Real> = *target - self.pts[i].pos;
+        let kick = toward * self.params.drag_k * self.params.dt;
+        self.pts[i].vel = self.pts[i].vel + kick;
+    }
+
+    /// Push every node within `impulse_radius` of node `i` straight out from
+    /// it, fading to nothing at the edge. Node `i` itself goes along its
+    /// surface normal, or along `ray` (the way the click went) if it isn't
+    /// on any triangles.
+    pub fn impulse(&mut self, i: usize, ray: &Vec3<Real>) {
+        if i >= self.pts.len() {
+            return;
+        }
+        let center = self.pts[i].pos;
+        let radius = self.params.impulse_radius;
+        // summed over the triangles round it, so as long as they're big
+        let normal = self.normals()[i];
+        let normal = if normal.norm() > 1e-12 {normal.normalize()} else {ray.normalize()};
+        for j in 0..self.pts.len() {
+            let dist = self.pts[j].pos.dist(&center);
+            if dist >= radius || self.pts[j].pinned {
+                continue;
+            }
+            let out = if dist < 1e-6 {normal} else {(self.pts[j].pos - center) / dist};
+            let kick = out * self.params.impulse * (1.0 - dist / radius);
+            self.pts[j].vel = self.pts[j].vel + kick;
+        }
+    }
+
+    /// Pin node `i` where it is, or let it go. Returns whether it's pinned.
+    pub fn toggle_pin(&mut self, i: usize) -> bool {
+        if i >= self.pts.len() {
+            return false;
+        }
+        self.pts[i].pinned = !self.pts[i].pinned;
+        self.pts[i].vel = na::zero();
+        self.pts[i].pinned
+    }
+}
+
+/// The point on a ray at `depth` along it.
+pub fn along(origin: &Pnt3<Real>, dir: &Vec3<Real>, depth: Real) -> Pnt3<Real> {
+    *origin + dir.normalize() * depth
+}
+
+/// How far along a ray the closest point to `pos` is.
+pub fn depth(origin: &Pnt3<Real>, dir: &Vec3<Real>, pos: &Pnt3<Real>) -> Real {
+    na::dot(&(*pos - *origin), &dir.normalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use na::{Pnt3, Vec3, Norm};
+    use testing::{node, anchored};
+
+    #[test]
+    fn kicks_the_centre_at_full_speed() {
+        let mut state = anchored(vec![node(0.0, 0.0, 0.0), node(0.01, 0.0, 0.0), node(0.0, 0.01, 0.0)]);
+        state.tris.push(Pnt3::new(10, 11, 12));
+        state.params.impulse = 0.5;
+        state.impulse(10, &Vec3::new(1.0, 0.0, 0.0));
+        // along the tiny triangle's normal, not the ray, and not scaled by
+        // the triangle's size
+        let vel = state.pts[10].vel;
+        assert!((vel.norm() - 0.5).abs() < 1e-5);
+        assert!(vel.x.abs() < 1e-5 && vel.y.abs() < 1e-5);
+    }
+
+    #[test]
+    fn kicks_a_loose_node_along_the_ray() {
+        let mut state = anchored(vec![node(0.0, 0.0, 0.0)]);
+        state.params.impulse = 0.5;
+        state.impulse(10, &Vec3::new(0.0, 0.0, 4.0));
+        let vel = state.pts[10].vel;
+        assert!((vel.z - 0.5).abs() < 1e-5 && vel.x == 0.0 && vel.y == 0.0);
+    }
+}
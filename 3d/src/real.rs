@@ -0,0 +1,37 @@
+extern crate nalgebra as na;
+use na::{Pnt3, Vec3};
+
+/// The scalar the simulation runs in. Build with `--features f64` for long
+/// runs where small forces get lost in f32 rounding; rendering always
+/// converts down to f32.
+#[cfg(not(feature = "f64"))]
+pub type Real = f32;
+#[cfg(feature = "f64")]
+pub type Real = f64;
+
+#[cfg(not(feature = "f64"))]
+pub use std::f32::{consts, INFINITY, NEG_INFINITY};
+#[cfg(feature = "f64")]
+pub use std::f64::{consts, INFINITY, NEG_INFINITY};
+
+/// Bits per scalar, recorded in saved states.
+#[cfg(not(feature = "f64"))]
+pub const PRECISION: u8 = 32;
+#[cfg(feature = "f64")]
+pub const PRECISION: u8 = 64;
+
+#[inline]
+pub fn to_render(p: &Pnt3<Real>) -> Pnt3<f32> {
+    Pnt3::new(p.x as f32, p.y as f32, p.z as f32)
+}
+
+/// Back from render space, for rays cast from the camera.
+#[inline]
+pub fn from_render(p: &Pnt3<f32>) -> Pnt3<Real> {
+    Pnt3::new(p.x as Real, p.y as Real, p.z as Real)
+}
+
+#[inline]
+pub fn dir_from_render(v: &Vec3<f32>) -> Vec3<Real> {
+    Vec3::new(v.x as Real, v.y as Real, v.z as Real)
+}
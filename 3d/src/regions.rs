@@ -0,0 +1,76 @@
+extern crate nalgebra as na;
+use na::FloatPnt;
+use state::State;
+use params::{Region, RegionRule};
+use real::Real;
+
+impl State {
+    /// Properties for a region id; ids past the end of `regions` act plain.
+    #[inline]
+    pub fn region(&self, id: u32) -> Region {
+        match self.params.regions.get(id as usize) {
+            Some(region) => *region,
+            None => Region::plain(),
+        }
+    }
+
+    /// Fraction of velocity node `i` keeps per unit time.
+    #[inline]
+    pub fn damp(&self, i: usize) -> Real {
+        self.region(self.pts[i].region).damp.unwrap_or(self.params.damp)
+    }
+
+    fn move_region(&mut self, i: usize, region: u32) -> bool {
+        // the anchors stay put
+        if i < 10 || self.pts[i].region == region {
+            return false;
+        }
+        self.pts[i].region = region;
+        true
+    }
+
+    /// Move nodes between regions by `region_rules`, then give every edge
+    /// whose ends now agree their region.
+    pub fn apply_region_rules(&mut self) {
+        let rules = self.params.region_rules.clone();
+        let mut changed = false;
+        for rule in rules.iter() {
+            match *rule {
+                RegionRule::Above{height, region} => {
+                    for i in 0..self.pts.len() {
+                        if self.pts[i].pos.y > height {
+                            changed |= self.move_region(i, region);
+                        }
+                    }
+                },
+                RegionRule::Older{age, region} => {
+                    for i in 0..self.pts.len() {
+                        if self.pts[i].age > age {
+                            changed |= self.move_region(i, region);
+                        }
+                    }
+                },
+                RegionRule::Patch{time, radius, seed, region} => {
+                    if time != self.time || self.pts.is_empty() {
+                        continue;
+                    }
+                    let center = self.pts[(seed as usize).wrapping_mul(2654435761) % self.pts.len()].pos;
+                    for i in 0..self.pts.len() {
+                        if self.pts[i].pos.dist(&center) < radius {
+                            changed |= self.move_region(i, region);
+                        }
+                    }
+                },
+            }
+        }
+        if !changed {
+            return;
+        }
+        for i in 0..self.edges.len() {
+            let (a, b) = self.edge_pts(i);
+            if self.pts[a].region == self.pts[b].region {
+                self.edges[i].region = self.pts[a].region;
+            }
+        }
+    }
+}
@@ -0,0 +1,270 @@
+extern crate nalgebra as na;
+use na::{Pnt3, Vec3, Norm, FloatPnt};
+use std::collections::{HashMap, HashSet};
+use real::consts::PI;
+use state::State;
+use real::Real;
+
+#[inline]
+fn key(a: usize, b: usize) -> (usize, usize) {
+    if a < b {(a, b)} else {(b, a)}
+}
+
+// whether going round `tri` takes you straight from `a` to `b`
+fn runs(tri: &Pnt3<u32>, a: usize, b: usize) -> bool {
+    let corners = [tri.x as usize, tri.y as usize, tri.z as usize];
+    (0..3).any(|k| corners[k] == a && corners[(k + 1) % 3] == b)
+}
+
+fn angle(at: Pnt3<Real>, a: Pnt3<Real>, b: Pnt3<Real>) -> Real {
+    let u: Vec3<Real> = a - at;
+    let v: Vec3<Real> = b - at;
+    let denom = u.norm() * v.norm();
+    if denom < 1e-12 {
+        return 0.0;
+    }
+    (na::dot(&u, &v) / denom).max(-1.0).min(1.0).acos()
+}
+
+impl State {
+    /// Collapse edges that have been squashed well below their rest length,
+    /// then flip edges that fail the Delaunay test. Returns the number of
+    /// (collapses, flips).
+    pub fn remesh(&mut self) -> (usize, usize) {
+        let collapsed = self.collapse_edges();
+        let flipped = self.flip_edges();
+        (collapsed, flipped)
+    }
+
+    fn neighbors(&self) -> Vec<HashSet<usize>> {
+        let mut found = vec![HashSet::new(); self.pts.len()];
+        for edge in self.edges.iter() {
+            found[edge.a].insert(edge.b);
+            found[edge.b].insert(edge.a);
+        }
+        found
+    }
+
+    // edge key -> corners opposite it, one per triangle
+    fn opposites(&self) -> HashMap<(usize, usize), Vec<(usize, usize)>> {
+        let mut opposite = HashMap::new();
+        for (t, tri) in self.tris.iter().enumerate() {
+            let corners = [tri.x as usize, tri.y as usize, tri.z as usize];
+            for k in 0..3 {
+                let (a, b, c) = (corners[k], corners[(k + 1) % 3], corners[(k + 2) % 3]);
+                opposite.entry(key(a, b)).or_insert(vec![]).push((t, c));
+            }
+        }
+        opposite
+    }
+
+    fn collapse_edges(&mut self) -> usize {
+        let neighbors = self.neighbors();
+        let opposites = self.opposites();
+        let mut touched = vec![false; self.pts.len()];
+        // every node maps to the one that survives it
+        let mut merged: Vec<usize> = (0..self.pts.len()).collect();
+        let mut count = 0;
+
+        for i in 0..self.edges.len() {
+            let (a, b) = self.edge_pts(i);
+            // the first ten nodes anchor the whole thing
+            if a < 10 || b < 10 || a == b || self.pts[a].pinned || self.pts[b].pinned {
+                continue;
+            }
+            // measured fresh: edges split this tick still have a curlen of 0
+            if self.pts[a].pos.dist(&self.pts[b].pos) >= self.edges[i].len * self.params.collapse_ratio {
+                continue;
+            }
+            if touched[a] || touched[b] || neighbors[a].iter().chain(neighbors[b].iter()).any(|&n| touched[n]) {
+                continue;
+            }
+            // link condition: the only shared neighbours are the opposite
+            // corners, or the collapse would pinch the surface
+            let corners: HashSet<usize> = match opposites.get(&key(a, b)) {
+                Some(list) => list.iter().map(|&(_, c)| c).collect(),
+                None => HashSet::new(),
+            };
+            if neighbors[a].intersection(&neighbors[b]).any(|n| !corners.contains(n)) {
+                continue;
+            }
+
+            let mid = self.pts[a].pos + (self.pts[b].pos - self.pts[a].pos) / 2.0;
+            self.pts[a].pos = mid;
+            self.pts[a].vel = (self.pts[a].vel + self.pts[b].vel) / 2.0;
+            self.pts[a].trunk = self.pts[a].trunk || self.pts[b].trunk;
+            self.pts[a].age = self.pts[a].age.max(self.pts[b].age);
+            let chem = self.pts[a].chem.iter().zip(self.pts[b].chem.iter()).map(|(x, y)| (x + y) / 2.0).collect();
+            self.pts[a].chem = chem;
+            self.pts[a].exposure = (self.pts[a].exposure + self.pts[b].exposure) / 2.0;
+            self.pts[a].dir = self.blend_dir(a, b);
+            // the survivor keeps its region, there's no halfway between two
+            if self.pts[a].right == b {
+                let next = self.pts[b].right;
+                self.pts[a].right = next;
+            }
+            if self.pts[a].left == b {
+                let prev = self.pts[b].left;
+                self.pts[a].left = prev;
+            }
+            merged[b] = a;
+            touched[a] = true;
+            touched[b] = true;
+            for &n in neighbors[a].iter().chain(neighbors[b].iter()) {
+                touched[n] = true;
+            }
+            count += 1;
+        }
+        if count == 0 {
+            return 0;
+        }
+
+        // compact the nodes, and point everything at the survivors
+        let mut index = vec![0; self.pts.len()];
+        let mut next = 0;
+        for i in 0..self.pts.len() {
+            if merged[i] == i {
+                index[i] = next;
+                next += 1;
+            }
+        }
+        let remap = |i: usize| index[merged[i]];
+        let old = ::std::mem::replace(&mut self.pts, vec![]);
+        for (i, mut node) in old.into_iter().enumerate() {
+            if merged[i] != i {
+                continue;
+            }
+            node.left = remap(node.left);
+            node.right = remap(node.right);
+            self.pts.push(node);
+        }
+
+        let mut seen = HashSet::new();
+        let old = ::std::mem::replace(&mut self.edges, vec![]);
+        for mut edge in old.into_iter() {
+            edge.a = remap(edge.a);
+            edge.b = remap(edge.b);
+            if edge.a == edge.b || !seen.insert(key(edge.a, edge.b)) {
+                continue;
+            }
+            self.edges.push(edge);
+        }
+
+        self.tris = self.tris.iter().map(|t|
+            Pnt3::new(remap(t.x as usize) as u32, remap(t.y as usize) as u32, remap(t.z as usize) as u32)
+        ).filter(|t| t.x != t.y && t.y != t.z && t.z != t.x).collect();
+
+        count
+    }
+
+    fn flip_edges(&mut self) -> usize {
+        let opposites = self.opposites();
+        let mut existing: HashSet<(usize, usize)> = self.edges.iter().map(|e| key(e.a, e.b)).collect();
+        let mut touched = vec![false; self.tris.len()];
+        let mut count = 0;
+
+        for i in 0..self.edges.len() {
+            let (a, b) = self.edge_pts(i);
+            // rim edges only have one triangle, and can't be flipped
+            let (t1, c, t2, d) = match opposites.get(&key(a, b)) {
+                Some(list) if list.len() == 2 => (list[0].0, list[0].1, list[1].0, list[1].1),
+                _ => continue,
+            };
+            if touched[t1] || touched[t2] || c == d || existing.contains(&key(c, d)) {
+                continue;
+            }
+            let (pa, pb, pc, pd) = (self.pts[a].pos, self.pts[b].pos, self.pts[c].pos, self.pts[d].pos);
+            if angle(pc, pa, pb) + angle(pd, pa, pb) <= PI + self.params.flip_angle {
+                continue;
+            }
+            existing.remove(&key(a, b));
+            existing.insert(key(c, d));
+            // keep the winding t1 had, so the normals don't turn over
+            let (a, b) = if runs(&self.tris[t1], a, b) {(a, b)} else {(b, a)};
+            self.tris[t1] = Pnt3::new(a as u32, d as u32, c as u32);
+            self.tris[t2] = Pnt3::new(b as u32, c as u32, d as u32);
+            touched[t1] = true;
+            touched[t2] = true;
+            let curlen = pc.dist(&pd);
+            self.edges[i].a = c;
+            self.edges[i].b = d;
+            self.edges[i].curlen = curlen;
+            self.edges[i].len = curlen.min(self.params.max_len);
+            count += 1;
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use na::Pnt3;
+    use state::State;
+    use testing::{node, edge, anchored};
+
+    fn facing_up(state: &State) -> bool {
+        state.tris.iter().all(|t| {
+            let (a, b, c) = (state.pts[t.x as usize].pos, state.pts[t.y as usize].pos, state.pts[t.z as usize].pos);
+            (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x) > 0.0
+        })
+    }
+
+    #[test]
+    fn fresh_splits_are_not_collapsed() {
+        let mut state = anchored(vec![node(0.0, 0.0, 0.0), node(1.0, 0.0, 0.0)]);
+        // as edge_split leaves it, with curlen not measured yet
+        state.edges.push(edge(10, 11, 1.0));
+        assert_eq!(state.collapse_edges(), 0);
+        assert_eq!(state.pts.len(), 12);
+    }
+
+    #[test]
+    fn collapse_merges_node_state() {
+        let mut a = node(0.0, 0.0, 0.0);
+        a.age = 3;
+        a.chem = vec![1.0];
+        a.exposure = 1.0;
+        let mut b = node(0.1, 0.0, 0.0);
+        b.trunk = true;
+        b.age = 7;
+        b.chem = vec![0.0];
+        b.exposure = 0.0;
+        let mut state = anchored(vec![a, b]);
+        state.edges.push(edge(10, 11, 1.0));
+        assert_eq!(state.collapse_edges(), 1);
+        assert_eq!(state.pts.len(), 11);
+        assert!(state.edges.is_empty());
+        let merged = &state.pts[10];
+        assert!((merged.pos.x - 0.05).abs() < 1e-6);
+        assert!(merged.trunk);
+        assert_eq!(merged.age, 7);
+        assert_eq!(merged.chem, vec![0.5]);
+        assert!((merged.exposure - 0.5).abs() < 1e-6);
+    }
+
+    fn flat_quad(tris: Vec<Pnt3<u32>>) -> State {
+        let mut state = anchored(vec![node(0.0, 0.0, 0.0), node(1.0, 0.0, 0.0), node(0.5, 0.1, 0.0), node(0.5, -0.1, 0.0)]);
+        for &(a, b) in [(10, 11), (10, 12), (11, 12), (10, 13), (11, 13)].iter() {
+            state.edges.push(edge(a, b, 1.0));
+        }
+        state.tris = tris;
+        assert!(facing_up(&state));
+        state
+    }
+
+    #[test]
+    fn flip_keeps_winding() {
+        let mut state = flat_quad(vec![Pnt3::new(10, 11, 12), Pnt3::new(11, 10, 13)]);
+        assert_eq!(state.flip_edges(), 1);
+        assert_eq!((state.edges[0].a, state.edges[0].b), (12, 13));
+        assert!(facing_up(&state));
+    }
+
+    #[test]
+    fn flip_keeps_winding_the_other_way_round() {
+        // the first triangle runs 11 -> 10, against the edge
+        let mut state = flat_quad(vec![Pnt3::new(10, 13, 11), Pnt3::new(12, 10, 11)]);
+        assert_eq!(state.flip_edges(), 1);
+        assert!(facing_up(&state));
+    }
+}
@@ -0,0 +1,217 @@
+extern crate nalgebra as na;
+extern crate kiss3d;
+extern crate image;
+
+use util;
+use real;
+use shaded;
+use imgcmd;
+use glcmd;
+use camscript::{self, Shot, CameraScript};
+use capture::{self, Resolution};
+use edges::EdgeStyle;
+use color::ColorScheme;
+
+use std::rc::Rc;
+use std::cmp;
+use std::cell::RefCell;
+use std::f32::consts::PI;
+use na::Pnt3;
+use state::State;
+use image::{ImageBuffer, Rgba};
+use kiss3d::window::Window;
+use kiss3d::resource::{Material, Mesh};
+
+/// The software renderer has no window to take its size from.
+const HEADLESS_SIZE: (usize, usize) = (800, 600);
+
+/// The standard views, as (label, yaw, elevation) in degrees. Top stops
+/// just short of straight down so "up" stays defined.
+const VIEWS: [(&'static str, f32, f32); 4] = [
+    ("FRONT", 0.0, 0.0),
+    ("SIDE", 90.0, 0.0),
+    ("TOP", 0.0, 89.0),
+    ("ISO", 45.0, 35.26),
+];
+
+/// Renders a state from a shot into RGB rows, top row first.
+trait Renderer {
+    fn size(&self) -> (usize, usize);
+    fn render(&mut self, state: &mut State, shot: &Shot, width: usize, height: usize) -> Vec<u8>;
+}
+
+/// Through the GL window, tiled to whatever size is asked for.
+struct GlRenderer<'a> {
+    window: &'a mut Window,
+    hollow: bool,
+    style: EdgeStyle,
+    supersample: usize,
+}
+
+impl<'a> GlRenderer<'a> {
+    fn new(window: &'a mut Window, state: &State, hollow: bool, style: EdgeStyle, scheme: ColorScheme, supersample: usize) -> GlRenderer<'a> {
+        if !hollow {
+            let mesh = Rc::new(RefCell::new(Mesh::new(state.render_coords(), state.tris.clone(), None, Some(state.scheme_colors(scheme)), false)));
+            let material = Rc::new(RefCell::new(Box::new(shaded::ShaderMaterial::default()) as Box<Material + 'static>));
+            let mut obj = window.add_mesh(mesh, na::one());
+            obj.enable_backface_culling(false);
+            obj.set_material(material);
+        } else if style.hidden {
+            glcmd::add_occluder(window, state);
+        }
+        GlRenderer{window: window, hollow: hollow, style: style, supersample: supersample}
+    }
+}
+
+impl<'a> Renderer for GlRenderer<'a> {
+    fn size(&self) -> (usize, usize) {
+        capture::framebuffer_size(&*self.window)
+    }
+
+    fn render(&mut self, state: &mut State, shot: &Shot, width: usize, height: usize) -> Vec<u8> {
+        let (hollow, style) = (self.hollow, self.style);
+        capture::capture(&mut *self.window, shot, width, height, self.supersample, &mut |window: &mut Window| {
+            if hollow {
+                glcmd::draw_depth_edges(window, state, &shot.eye, 180.0, &style);
+            }
+        })
+    }
+}
+
+/// Edges only, drawn with `aaline`; needs no display at all.
+struct SoftRenderer {
+    style: EdgeStyle,
+    supersample: usize,
+}
+
+impl Renderer for SoftRenderer {
+    fn size(&self) -> (usize, usize) {
+        HEADLESS_SIZE
+    }
+
+    fn render(&mut self, state: &mut State, shot: &Shot, width: usize, height: usize) -> Vec<u8> {
+        let ss = if self.supersample > 0 {self.supersample} else {1};
+        let mut img = ImageBuffer::from_pixel((width * ss) as u32, (height * ss) as u32, Rgba([255u8, 255, 255, 255]));
+        imgcmd::draw_shot(&mut img, state, 180.0, shot, self.style, ss as f32);
+        let rgb: Vec<u8> = img.into_raw().chunks(4).flat_map(|px| px[..3].iter().cloned()).collect();
+        capture::downsample(rgb, width, height, ss)
+    }
+}
+
+fn renderer<'a>(window: Option<&'a mut Window>, state: &State, hollow: bool, style: EdgeStyle, scheme: ColorScheme, supersample: usize) -> Box<Renderer + 'a> {
+    match window {
+        Some(window) => Box::new(GlRenderer::new(window, state, hollow, style, scheme, supersample)),
+        None => Box::new(SoftRenderer{style: style, supersample: supersample}),
+    }
+}
+
+fn render_bounds(state: &State) -> (Pnt3<f32>, Pnt3<f32>) {
+    let (min, max) = state.bounds();
+    (real::to_render(&min), real::to_render(&max))
+}
+
+/// How far round the turntable goes and how. A camera script, if there is
+/// one, takes over from the orbit, with its time counted in frames.
+pub struct Turntable {
+    pub frames: usize,
+    pub degrees: f32,
+    pub elevation: f32,
+    pub script: Option<CameraScript>,
+}
+
+/// Orbit the finished state, writing `{outfile}-0000.png` and on. A full
+/// 360 leaves off the last frame, which would repeat the first, so the
+/// frames loop. Without a window it uses the software renderer.
+pub fn turntable(window: Option<&mut Window>, infile: String, outfile: String, turn: Turntable, resolution: Option<Resolution>, hollow: bool, style: EdgeStyle, scheme: ColorScheme) {
+    let mut state = util::load_state(infile);
+    let bounds = render_bounds(&state);
+    let supersample = resolution.map(|res| res.supersample).unwrap_or(1);
+    let mut renderer = renderer(window, &state, hollow, style, scheme, supersample);
+    let (width, height) = resolution.map(|res| (res.width, res.height)).unwrap_or(renderer.size());
+
+    let looped = turn.degrees.abs() >= 360.0;
+    let steps = if looped || turn.frames < 2 {turn.frames} else {turn.frames - 1};
+    for i in 0..turn.frames {
+        let shot = match turn.script {
+            Some(ref script) => script.shot(i as i32, Some(bounds)),
+            None => {
+                let yaw = if steps > 0 {turn.degrees * i as f32 / steps as f32} else {0.0};
+                camscript::orbit(bounds, yaw * PI / 180.0, turn.elevation * PI / 180.0)
+            },
+        };
+        let buf = renderer.render(&mut state, &shot, width, height);
+        capture::save_png(buf, width, height, format!("{}-{:04}.png", outfile, i));
+    }
+}
+
+/// Front, side, top and iso views in a labelled two by two grid, each view
+/// the given size. Without a window it uses the software renderer.
+pub fn views(window: Option<&mut Window>, infile: String, outfile: String, resolution: Option<Resolution>, hollow: bool, style: EdgeStyle, scheme: ColorScheme) {
+    let mut state = util::load_state(infile);
+    let bounds = render_bounds(&state);
+    let supersample = resolution.map(|res| res.supersample).unwrap_or(1);
+    let mut renderer = renderer(window, &state, hollow, style, scheme, supersample);
+    let (width, height) = resolution.map(|res| (res.width, res.height)).unwrap_or(renderer.size());
+
+    let full_w = width * 2;
+    let mut sheet = vec![0u8; full_w * height * 2 * 3];
+    let scale = cmp::max(2, height / 150);
+    for (k, &(label, yaw, elevation)) in VIEWS.iter().enumerate() {
+        let shot = camscript::orbit(bounds, yaw * PI / 180.0, elevation * PI / 180.0);
+        let mut buf = renderer.render(&mut state, &shot, width, height);
+        stamp(&mut buf, width, label, 2 * scale, 2 * scale, scale);
+        let (left, top) = ((k % 2) * width, (k / 2) * height);
+        for row in 0..height {
+            let to = ((top + row) * full_w + left) * 3;
+            sheet[to..to + width * 3].clone_from_slice(&buf[row * width * 3..(row + 1) * width * 3]);
+        }
+    }
+    capture::save_png(sheet, full_w, height * 2, outfile);
+}
+
+// 3x5 glyphs for the view labels, a row per entry, high bit on the left
+fn glyph(c: char) -> Option<[u8; 5]> {
+    match c {
+        'D' => Some([6, 5, 5, 5, 6]),
+        'E' => Some([7, 4, 6, 4, 7]),
+        'F' => Some([7, 4, 6, 4, 4]),
+        'I' => Some([7, 2, 2, 2, 7]),
+        'N' => Some([6, 5, 5, 5, 5]),
+        'O' => Some([2, 5, 5, 5, 2]),
+        'P' => Some([6, 5, 6, 4, 4]),
+        'R' => Some([6, 5, 6, 5, 5]),
+        'S' => Some([3, 4, 2, 1, 6]),
+        'T' => Some([7, 2, 2, 2, 2]),
+        _ => None,
+    }
+}
+
+/// Write `text` into RGB rows `width` wide, in dark grey blocks `scale`
+/// pixels across, from (`x`, `y`) at the top left.
+fn stamp(buf: &mut [u8], width: usize, text: &str, x: usize, y: usize, scale: usize) {
+    let height = buf.len() / 3 / width;
+    for (n, c) in text.chars().enumerate() {
+        let rows = match glyph(c) {
+            Some(rows) => rows,
+            None => continue,
+        };
+        let left = x + n * 4 * scale;
+        for (j, bits) in rows.iter().enumerate() {
+            for i in 0..3 {
+                if bits & (4 >> i) == 0 {
+                    continue;
+                }
+                for py in y + j * scale..y + (j + 1) * scale {
+                    for px in left + i * scale..left + (i + 1) * scale {
+                        if px < width && py < height {
+                            let at = (py * width + px) * 3;
+                            buf[at] = 40;
+                            buf[at + 1] = 40;
+                            buf[at + 2] = 40;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use rustc_serialize::json::{self, Json};
+use std::fs::File;
+use std::io::{Read, Write};
+use params::Params;
+use real::Real;
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone, Copy, Debug)]
+pub enum Ease {
+    Linear,
+    Step,
+}
+
+/// Keyframes for one param, by name (`stop.max_nodes` reaches into nested
+/// params). Keys are (time, value) pairs, sorted by time.
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone, Debug)]
+pub struct Track {
+    pub param: String,
+    pub ease: Ease,
+    pub keys: Vec<(i32, Real)>,
+}
+
+impl Track {
+    /// The value at `time`, from the last key at or before it. Keys sharing
+    /// a time make a jump there, and the last of them wins.
+    pub fn value(&self, time: i32) -> Real {
+        let k = match self.keys.iter().rposition(|key| key.0 <= time) {
+            Some(k) => k,
+            None => return self.keys[0].1,
+        };
+        let (t0, v0) = self.keys[k];
+        match self.keys.get(k + 1) {
+            Some(&(t1, v1)) if self.ease == Ease::Linear => v0 + (v1 - v0) * (time - t0) as Real / (t1 - t0) as Real,
+            _ => v0,
+        }
+    }
+}
+
+fn lookup<'a>(json: &'a mut Json, path: &str) -> Option<&'a mut Json> {
+    let mut current = json;
+    for part in path.split('.') {
+        let here = current;
+        current = match *here {
+            Json::Object(ref mut obj) => match obj.get_mut(part) {
+                Some(next) => next,
+                None => return None,
+            },
+            _ => return None,
+        };
+    }
+    Some(current)
+}
+
+// keeps the JSON type of whatever was there, so integer and flag params
+// still decode
+fn set(json: &mut Json, path: &str, value: Real) -> bool {
+    match lookup(json, path) {
+        Some(slot) => {
+            *slot = match *slot {
+                Json::U64(_) => Json::U64(value.round().max(0.0) as u64),
+                Json::I64(_) => Json::I64(value.round() as i64),
+                Json::Boolean(_) => Json::Boolean(value != 0.0),
+                Json::F64(_) => Json::F64(value as f64),
+                // an unset Option: whole numbers go in as integers, which
+                // decode into float params too
+                Json::Null if value.fract() == 0.0 && value >= 0.0 => Json::U64(value as u64),
+                Json::Null if value.fract() == 0.0 => Json::I64(value as i64),
+                Json::Null => Json::F64(value as f64),
+                _ => return false,
+            };
+            true
+        },
+        None => false,
+    }
+}
+
+/// The current value of a numeric param, named the way tracks name them.
+pub fn get(params: &Params, path: &str) -> Option<Real> {
+    let mut json = params.to_json();
+    lookup(&mut json, path).and_then(|value| value.as_f64()).map(|value| value as Real)
+}
+
+/// Whether any track moves between the tick before `time` and `time`, so
+/// `apply` can be skipped on the ticks where nothing would change.
+pub fn changes(tracks: &[Track], time: i32) -> bool {
+    tracks.iter().any(|track| track.value(time) != track.value(time - 1))
+}
+
+/// Params with every track evaluated at `time`.
+pub fn apply(tracks: &[Track], params: &Params, time: i32) -> Params {
+    let mut json = params.to_json();
+    for track in tracks {
+        set(&mut json, &track.param, track.value(time));
+    }
+    json::decode(&json.to_string()).ok().expect("Applying param schedule")
+}
+
+/// Params with just `path` set to `value`.
+pub fn with(params: &Params, path: &str, value: Real) -> Params {
+    let mut json = params.to_json();
+    set(&mut json, path, value);
+    json::decode(&json.to_string()).ok().expect("Setting a param")
+}
+
+/// Read a JSON list of tracks, checking that each names a real param.
+pub fn load(path: String, params: &Params) -> Vec<Track> {
+    let mut text = String::new();
+    File::open(path.clone()).ok().expect(&format!("Can't read schedule: {}", path))
+        .read_to_string(&mut text).ok().expect(&format!("Can't read schedule: {}", path));
+    let mut tracks: Vec<Track> = json::decode(&text).ok().expect(&format!("Unable to load schedule - is the format right? {}", path));
+    for track in tracks.iter_mut() {
+        if track.keys.is_empty() {
+            panic!("Schedule for {} has no keys", track.param);
+        }
+        track.keys.sort_by(|a, b| a.0.cmp(&b.0));
+        // every key has to make params that decode, or `apply` would fail
+        // mid-run
+        for &(time, value) in track.keys.iter() {
+            let mut json = params.to_json();
+            if !set(&mut json, &track.param, value) {
+                panic!("Can't schedule unknown or non-numeric param {}", track.param);
+            }
+            if json::decode::<Params>(&json.to_string()).is_err() {
+                panic!("Can't set {} to {} (at tick {}), it doesn't fit the param", track.param, value, time);
+            }
+        }
+    }
+    tracks
+}
+
+/// Write tracks out the way `load` reads them.
+pub fn save(tracks: &[Track], path: String) {
+    let text = json::encode(&tracks).ok().expect("Encoding schedule");
+    File::create(path.clone()).ok().expect(&format!("Can't write schedule: {}", path))
+        .write_all(text.as_bytes()).ok().expect(&format!("Can't write schedule: {}", path));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Track, Ease, set, get, apply, changes};
+    use params::Params;
+    use real::Real;
+
+    fn track(ease: Ease, keys: Vec<(i32, Real)>) -> Track {
+        Track{param: "max_len".to_string(), ease: ease, keys: keys}
+    }
+
+    #[test]
+    fn holds_the_ends() {
+        let track = track(Ease::Linear, vec![(10, 1.0), (20, 3.0)]);
+        assert_eq!(track.value(0), 1.0);
+        assert_eq!(track.value(10), 1.0);
+        assert_eq!(track.value(20), 3.0);
+        assert_eq!(track.value(30), 3.0);
+    }
+
+    #[test]
+    fn eases() {
+        assert_eq!(track(Ease::Linear, vec![(10, 1.0), (20, 3.0)]).value(15), 2.0);
+        assert_eq!(track(Ease::Step, vec![(10, 1.0), (20, 3.0)]).value(19), 1.0);
+    }
+
+    #[test]
+    fn jumps_take_the_last_key() {
+        let track = track(Ease::Step, vec![(5, 1.0), (5, 2.0), (8, 4.0)]);
+        assert_eq!(track.value(4), 1.0);
+        assert_eq!(track.value(5), 2.0);
+        assert_eq!(track.value(7), 2.0);
+        let linear = Track{ease: Ease::Linear, ..track};
+        assert_eq!(linear.value(5), 2.0);
+        assert_eq!(linear.value(6), 2.0 + 2.0 / 3.0);
+    }
+
+    #[test]
+    fn sets_keep_types() {
+        let mut json = Params::default().to_json();
+        assert!(set(&mut json, "max_len", 2.5));
+        assert!(set(&mut json, "stop.max_nodes", 300.0));
+        assert!(!set(&mut json, "no_such_param", 1.0));
+        let params: Params = ::rustc_serialize::json::decode(&json.to_string()).unwrap();
+        assert_eq!(params.max_len, 2.5);
+        assert_eq!(params.stop.max_nodes, Some(300));
+    }
+
+    #[test]
+    fn applies_an_unset_option() {
+        let tracks = vec![Track{param: "stop.max_nodes".to_string(), ease: Ease::Step, keys: vec![(0, 100.0)]}];
+        let params = apply(&tracks, &Params::default(), 1);
+        assert_eq!(params.stop.max_nodes, Some(100));
+        assert_eq!(get(&params, "stop.max_nodes"), Some(100.0));
+    }
+
+    #[test]
+    fn only_changes_between_keys() {
+        let tracks = vec![track(Ease::Step, vec![(10, 1.0), (20, 3.0)])];
+        assert!(!changes(&tracks, 5));
+        assert!(!changes(&tracks, 15));
+        assert!(changes(&tracks, 20));
+        assert!(!changes(&tracks, 21));
+    }
+}
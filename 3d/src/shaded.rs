@@ -22,6 +22,7 @@ pub struct ShaderMaterial {
     scale:     ShaderUniform<Mat3<f32>>,
     //time:      ShaderUniform<f32>,
     time_local: i32,
+    depth_only: bool,
 }
 
 impl ShaderMaterial {
@@ -39,6 +40,7 @@ impl ShaderMaterial {
             view:      shader.get_uniform("view").unwrap(),
             //time:      shader.get_uniform("time").unwrap(),
             time_local:0,
+            depth_only: false,
             shader:    shader
         }
     }
@@ -47,6 +49,14 @@ impl ShaderMaterial {
         ShaderMaterial::new(UVS_VERTEX_SRC, UVS_FRAGMENT_SRC)
     }
 
+    /// Writes the surface's depth but no colour, pushed back a little, so
+    /// lines drawn after are hidden behind it but not where they lie on it.
+    pub fn depth_only() -> ShaderMaterial {
+        let mut material = ShaderMaterial::default();
+        material.depth_only = true;
+        material
+    }
+
     pub fn inc_time(&mut self) {
         self.time_local += 1;
     }
@@ -106,6 +116,12 @@ impl Material for ShaderMaterial {
         mesh.bind_uvs(&mut self.uvs);
         mesh.bind_faces();
 
+        if self.depth_only {
+            verify!(gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE));
+            verify!(gl::Enable(gl::POLYGON_OFFSET_FILL));
+            verify!(gl::PolygonOffset(1.0, 1.0));
+        }
+
         unsafe {
             gl::DrawElements(gl::TRIANGLES,
                              mesh.num_pts() as GLint,
@@ -113,6 +129,11 @@ impl Material for ShaderMaterial {
                              ptr::null());
         }
 
+        if self.depth_only {
+            verify!(gl::Disable(gl::POLYGON_OFFSET_FILL));
+            verify!(gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE));
+        }
+
         mesh.unbind();
 
         self.position.disable();
@@ -143,7 +164,11 @@ vec3 hsv2rgb(vec3 c) {
 
 void main() {
     // float vtime = sin(time / 25.0) * 0.4 + 0.5;
-    if (uvs.y == 0) {
+    if (uvs.y > 1.5) {
+        // heat map, blue (0) to red (1)
+        uv_as_a_color = hsv2rgb(vec3(0.66 * (1.0 - uvs.x), 1.0, 0.8));
+        opacity = 0.9;
+    } else if (uvs.y == 0) {
         if (uvs.x > 0.9) {
             uv_as_a_color = hsv2rgb(vec3(0.33, 1.0, 0.5 + (uvs.x - 0.9) * 2));
         } else {
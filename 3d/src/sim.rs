@@ -0,0 +1,414 @@
+extern crate nalgebra as na;
+extern crate time;
+use na::{Pnt2, Pnt3, Vec3};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Sender, Receiver, TryRecvError};
+use std::thread;
+use state::State;
+use pick::{Picked, Hit};
+use poke;
+use schedule;
+use tweak::TWEAKS;
+use color::ColorScheme;
+use history::History;
+use util;
+use real;
+use real::Real;
+
+/// The numbers behind the viewer's HUD.
+#[derive(Clone, Copy, Debug)]
+pub struct Counts {
+    pub nodes: usize,
+    pub edges: usize,
+    pub tris: usize,
+    pub mean_strain: f32,
+    pub running: bool,
+}
+
+/// What the render thread needs of one tick: already converted for GL, so
+/// uploading it is just a copy.
+pub struct Snapshot {
+    pub time: i32,
+    pub coords: Vec<Pnt3<f32>>,
+    pub tris: Vec<Pnt3<u32>>,
+    pub colors: Vec<Pnt2<f32>>,
+    /// Edges as (from, to, colour), only filled in while wanted.
+    pub lines: Vec<(Pnt3<f32>, Pnt3<f32>, Pnt3<f32>)>,
+    pub bounds: (Pnt3<f32>, Pnt3<f32>),
+    pub counts: Counts,
+    pub picked: Option<Picked>,
+    pub pins: Vec<Pnt3<f32>>,
+    /// A dragged node and where it's being pulled to.
+    pub drag: Option<(Pnt3<f32>, Pnt3<f32>)>,
+    /// Pick commands handled so far, and whether the last one grabbed a
+    /// node, so the viewer only takes the mouse from the camera for a real
+    /// drag.
+    pub picks: usize,
+    pub grabbed: bool,
+    /// Values of `tweak::TWEAKS`, while the panel is up.
+    pub tweaks: Vec<Real>,
+    pub ticks_per_sec: f32,
+}
+
+impl Snapshot {
+    fn of(state: &State, scheme: ColorScheme, lines: bool, running: bool, picked: Option<Hit>, ticks_per_sec: f32) -> Snapshot {
+        let (min, max) = state.bounds();
+        // `curlen` is fresh from the tick's `measure`
+        let strain: Real = state.edges.iter().map(|e| if e.len > 0.0 {e.curlen / e.len} else {1.0}).fold(0.0, |a, b| a + b);
+        Snapshot {
+            time: state.time,
+            coords: state.render_coords(),
+            tris: state.tris.clone(),
+            colors: state.scheme_colors(scheme),
+            lines: if lines {
+                (0..state.num_edges()).map(|i| {
+                    let (a, b) = state.edge_pts(i);
+                    (state.render_pos(a), state.render_pos(b), state.edge_color(i, 180.0))
+                }).collect()
+            } else {
+                vec![]
+            },
+            bounds: (real::to_render(&min), real::to_render(&max)),
+            counts: Counts {
+                nodes: state.pts.len(),
+                edges: state.edges.len(),
+                tris: state.tris.len(),
+                mean_strain: if state.edges.is_empty() {1.0} else {(strain / state.edges.len() as Real) as f32},
+                running: running,
+            },
+            // indices shift with remeshing and rewinding, so it may be gone
+            picked: picked.and_then(|hit| if hit.node < state.pts.len() {Some(state.picked(&hit))} else {None}),
+            pins: state.pts.iter().filter(|n| n.pinned).map(|n| real::to_render(&n.pos)).collect(),
+            drag: None,
+            picks: 0,
+            grabbed: false,
+            tweaks: vec![],
+            ticks_per_sec: ticks_per_sec,
+        }
+    }
+}
+
+pub enum Command {
+    Toggle,
+    /// Tick once, while paused.
+    Step,
+    /// Most ticks to run between frames; 0 for as many as it can.
+    PerFrame(usize),
+    /// Write the state out to a timestamped file.
+    Save,
+    Lines(bool),
+    /// Pick the node under a ray from the camera, or clear the pick. A
+    /// picked node can then be dragged until released.
+    Pick(Pnt3<f32>, Vec3<f32>),
+    /// Pull the picked node toward the cursor ray, at the depth it was
+    /// picked at.
+    Drag(Pnt3<f32>, Vec3<f32>),
+    Release,
+    /// Push out the nodes around the one under the ray.
+    Poke(Pnt3<f32>, Vec3<f32>),
+    /// Pin or unpin the node under the ray.
+    Pin(Pnt3<f32>, Vec3<f32>),
+    /// Move one of `tweak::TWEAKS` by this many of its steps.
+    Tweak(usize, Real),
+    Panel(bool),
+    Reload,
+    Back,
+    Forward,
+    Branch,
+    Scheme(ColorScheme),
+    /// Wait for every snapshot to be taken before ticking again, so a
+    /// recording gets every frame.
+    Lockstep(bool),
+}
+
+/// Runs the simulation on its own thread. Only the latest snapshot is
+/// kept; the render thread takes it whenever it's ready for another.
+pub struct Sim {
+    commands: Sender<Command>,
+    latest: Arc<Mutex<Option<Snapshot>>>,
+}
+
+struct Worker {
+    state: State,
+    history: History,
+    setup: Box<Fn(&mut State) + Send>,
+    infile: Option<String>,
+    outfile: String,
+    max_time: i32,
+    scheme: ColorScheme,
+    running: bool,
+    lockstep: bool,
+    per_frame: usize,
+    // ticks since the render thread last took a snapshot
+    pending: usize,
+    lines: bool,
+    picked: Option<Hit>,
+    // the dragged node and its depth along the ray, and where it's going
+    grab: Option<(usize, Real)>,
+    target: Option<Pnt3<Real>>,
+    picks: usize,
+    panel: bool,
+    latest: Arc<Mutex<Option<Snapshot>>>,
+    // the state has moved on since the last snapshot
+    stale: bool,
+    ticks_per_sec: f32,
+}
+
+impl Sim {
+    pub fn spawn(max_time: i32, outfile: String, infile: Option<String>, scheme: ColorScheme, history: History, lockstep: bool, lines: bool, setup: Box<Fn(&mut State) + Send>) -> Sim {
+        let (commands, receiver) = mpsc::channel();
+        let latest = Arc::new(Mutex::new(None));
+        let shared = latest.clone();
+        thread::spawn(move || {
+            let state = util::load_maybe(infile.clone(), 10, &*setup);
+            let mut worker = Worker {
+                state: state,
+                history: history,
+                setup: setup,
+                infile: infile,
+                outfile: outfile,
+                max_time: max_time,
+                scheme: scheme,
+                running: true,
+                lockstep: lockstep,
+                per_frame: 0,
+                pending: 0,
+                lines: lines,
+                picked: None,
+                grab: None,
+                target: None,
+                picks: 0,
+                panel: false,
+                latest: shared,
+                stale: false,
+                ticks_per_sec: 0.0,
+            };
+            worker.run(receiver);
+        });
+        Sim {
+            commands: commands,
+            latest: latest,
+        }
+    }
+
+    pub fn send(&self, command: Command) {
+        self.commands.send(command).ok().expect("Simulation thread died");
+    }
+
+    /// The newest snapshot, if there's been one since the last take.
+    pub fn take(&self) -> Option<Snapshot> {
+        self.latest.lock().ok().expect("Simulation thread panicked").take()
+    }
+}
+
+impl Worker {
+    fn publish(&mut self) {
+        let mut snap = Snapshot::of(&self.state, self.scheme, self.lines, self.running, self.picked, self.ticks_per_sec);
+        if let (Some((i, _)), Some(target)) = (self.grab, self.target) {
+            if i < self.state.pts.len() {
+                snap.drag = Some((self.state.render_pos(i), real::to_render(&target)));
+            }
+        }
+        snap.picks = self.picks;
+        snap.grabbed = self.grab.is_some();
+        if self.panel {
+            snap.tweaks = self.state.tweak_values();
+        }
+        *self.latest.lock().ok().expect("Render thread panicked") = Some(snap);
+        self.stale = false;
+    }
+
+    fn taken(&self) -> bool {
+        self.latest.lock().ok().expect("Render thread panicked").is_none()
+    }
+
+    /// Publish once the render thread has taken the last snapshot, rather
+    /// than build one per tick that gets replaced unseen. While paused it
+    /// always publishes, so commands show straight away.
+    fn offer(&mut self) {
+        self.stale = true;
+        if !self.running || self.taken() {
+            self.publish();
+        }
+    }
+
+    fn waiting(&mut self) -> bool {
+        if self.taken() {
+            self.pending = 0;
+        }
+        let limit = if self.lockstep {1} else {self.per_frame};
+        limit > 0 && self.pending >= limit
+    }
+
+    fn tick(&mut self) {
+        if let (Some((i, _)), Some(target)) = (self.grab, self.target) {
+            self.state.pull(i, &target);
+        }
+        self.state.tick();
+        self.history.record(&self.state);
+        self.pending += 1;
+    }
+
+    fn handle(&mut self, command: Command) {
+        match command {
+            Command::Toggle => {
+                // resuming from a rewound point carries on from there
+                self.history.branch();
+                self.running = !self.running;
+            },
+            Command::Step => {
+                if !self.running && self.state.time < self.max_time {
+                    self.history.branch();
+                    self.tick();
+                }
+            },
+            Command::PerFrame(count) => self.per_frame = count,
+            Command::Save => {
+                let stamp = time::strftime("%Y%m%d-%H%M%S", &time::now()).ok().expect("Formatting the time");
+                let path = format!("{}-{}", self.outfile, stamp);
+                util::write_out(&self.state, path.clone());
+                println!("Saved {} at {}", path, self.state.time);
+                // so the session's tweaks can be replayed with --schedule
+                if !self.state.schedule.is_empty() {
+                    schedule::save(&self.state.schedule, path.clone() + ".schedule.json");
+                    println!("Saved {}.schedule.json", path);
+                }
+            },
+            Command::Lines(lines) => self.lines = lines,
+            Command::Pick(origin, dir) => {
+                self.picked = self.state.hit(&origin, &dir);
+                self.picks += 1;
+                self.grab = None;
+                self.target = None;
+                if let Some(hit) = self.picked {
+                    for line in self.state.describe(&hit) {
+                        println!("{}", line);
+                    }
+                    let i = hit.node;
+                    let depth = poke::depth(&real::from_render(&origin), &real::dir_from_render(&dir), &self.state.pts[i].pos);
+                    self.grab = Some((i, depth));
+                }
+            },
+            Command::Drag(origin, dir) => {
+                if let Some((_, depth)) = self.grab {
+                    self.target = Some(poke::along(&real::from_render(&origin), &real::dir_from_render(&dir), depth));
+                }
+            },
+            Command::Release => {
+                self.grab = None;
+                self.target = None;
+            },
+            Command::Poke(origin, dir) => {
+                if let Some(i) = self.state.pick(&origin, &dir) {
+                    self.state.impulse(i, &real::dir_from_render(&dir));
+                }
+            },
+            Command::Pin(origin, dir) => {
+                if let Some(i) = self.state.pick(&origin, &dir) {
+                    let pinned = self.state.toggle_pin(i);
+                    println!("{} node {}", if pinned {"Pinned"} else {"Unpinned"}, i);
+                }
+            },
+            Command::Reload => {
+                self.state = util::load_maybe(self.infile.clone(), 10, &*self.setup);
+            },
+            Command::Back => {
+                if let Some(past) = self.history.back(&self.state) {
+                    self.state = past;
+                    self.running = false;
+                    println!("Rewound to {}", self.state.time);
+                }
+            },
+            Command::Forward => {
+                if let Some(later) = self.history.forward() {
+                    self.state = later;
+                    println!("Forward to {}", self.state.time);
+                }
+            },
+            Command::Branch => {
+                if self.history.rewound() {
+                    self.history.branch();
+                    (self.setup)(&mut self.state);
+                    self.running = true;
+                    println!("Branched at {}", self.state.time);
+                }
+            },
+            Command::Tweak(which, steps) => {
+                let (param, step) = TWEAKS[which];
+                let value = schedule::get(&self.state.params, param).unwrap_or(0.0);
+                self.state.tweak(param, (value + step * steps).max(0.0));
+            },
+            Command::Panel(panel) => self.panel = panel,
+            Command::Scheme(scheme) => self.scheme = scheme,
+            Command::Lockstep(lockstep) => self.lockstep = lockstep,
+        }
+    }
+
+    fn run(&mut self, commands: Receiver<Command>) {
+        let start = time::get_time();
+        if self.state.time == 0 {
+            while self.state.tris.len() == 0 {
+                self.state.tick();
+            }
+        }
+        self.publish();
+        let mut counted = 0;
+        let mut since = time::precise_time_s();
+        loop {
+            if self.stale && self.taken() {
+                self.publish();
+            }
+            // block while there's nothing to do, rather than spin
+            let next = if self.running && !self.waiting() {
+                match commands.try_recv() {
+                    Ok(command) => Some(command),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            } else if self.running {
+                thread::sleep_ms(1);
+                match commands.try_recv() {
+                    Ok(command) => Some(command),
+                    Err(TryRecvError::Empty) => continue,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            } else {
+                match commands.recv() {
+                    Ok(command) => Some(command),
+                    Err(_) => return,
+                }
+            };
+            if let Some(command) = next {
+                self.handle(command);
+                self.offer();
+                continue;
+            }
+            if self.state.time >= self.max_time {
+                if self.state.time == self.max_time {
+                    println!("Output");
+                    util::write_out(&self.state, self.outfile.clone());
+                    self.state.time += 1;
+                }
+                self.running = false;
+                self.publish();
+                continue;
+            }
+
+            self.tick();
+            counted += 1;
+            let now = time::precise_time_s();
+            if now - since >= 1.0 {
+                self.ticks_per_sec = (counted as f64 / (now - since)) as f32;
+                counted = 0;
+                since = now;
+            }
+            self.offer();
+
+            if self.state.time % 50 == 0 {
+                util::write_out(&self.state, self.outfile.clone() + ".tmp");
+                let diff = time::get_time() - start;
+                println!("At {} : {}", self.state.time, diff);
+            }
+        }
+    }
+}
@@ -1,64 +1,75 @@
 #![allow(dead_code)]
 
 extern crate nalgebra as na;
-use std::f32;
+use real::{self, Real};
 use na::{Pnt2, Vec3, Pnt3, FloatPnt, Norm};
-use std::collections::HashMap;
+use params::{Params, Model, Env};
+use grid::Grid;
+use stats::TickStats;
+use schedule::{self, Track};
+use field::{Field, Fields};
 
 //let SHOW_POINTS = false;
 //let COLOR_SCHEME = 'age';
 //const RANDOM = false;
 
-const TOLERANCE: f32 = 0.001;
-const DAMP: f32 = 0.75;
-const STICK_K: f32 = 0.09;
-const AVOID_K: f32 = 0.02;
-
-const MAX_LEN: f32 = 0.5;
-const TOO_CROWDED: usize = 34; // neighbors
-const MIN_CROWD: i32 = 5;
-const TOO_DEAD: i32 = 100;
-const DEAD_MOTION: f32 = 0.0001;
-const CLOSE_DIST: f32 = 2.0;
-const PUSH_DIST: f32 = 0.8;
-const GROW_SPEED: f32 = 0.01;
-const MAX_SPEED: f32  = 0.02;
-const GRAVITY: f32 = 0.01;
-const GRAV_TOP: f32 = 10.0;
-const GRAV_BOTTOM: f32 = 7.0;
-
-#[derive(RustcEncodable, RustcDecodable, PartialEq)]
-struct Edge {
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone)]
+pub struct Edge {
     pub a: usize,
     pub b: usize,
-    age: usize,
-    len: f32,
-    curlen: f32,
+    pub age: usize,
+    pub len: Real,
+    pub curlen: Real,
+    pub region: u32,
 }
 
-#[derive(RustcEncodable, RustcDecodable, PartialEq)]
-struct Node {
-    pos: Pnt3<f32>,
-    vel: Vec3<f32>,
-    nclose: usize,
-    siblings: usize,
-    age: usize,
-    dead: i32,
-    left: usize,
-    right: usize,
-    trunk: bool,
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone)]
+pub struct Node {
+    pub pos: Pnt3<Real>,
+    pub vel: Vec3<Real>,
+    pub nclose: usize,
+    pub siblings: usize,
+    pub age: usize,
+    pub dead: i32,
+    pub left: usize,
+    pub right: usize,
+    pub trunk: bool,
+    pub chem: Vec<Real>,
+    pub exposure: Real,
+    pub dir: Vec3<Real>, // preferred growth direction
+    pub region: u32,
+    pub pinned: bool, // held in place from the viewer
+}
+
+pub struct Forces {
+    pub accel: Vec<Vec3<Real>>,
+    pub nclose: Vec<usize>,
 }
 
 pub trait DrawState {
     fn draw_state(&mut self, state: &mut State, off: f32);
 }
 
-#[derive(RustcEncodable, RustcDecodable, PartialEq)]
+/// Save file format: "GR" and a version number. Bump the version whenever
+/// `State`, or anything saved inside it, changes shape, since bincode has
+/// no field names to fall back on.
+pub const FORMAT: u32 = 0x4752_0001;
+
+#[derive(RustcEncodable, RustcDecodable, PartialEq, Clone)]
 pub struct State {
+    // these two must stay first, so loading can check them before decoding
+    // the rest
+    pub format: u32,
+    pub precision: u8,
     pub time: i32,
-    pts: Vec<Node>,// = [Pnt3{x: 0.0, y: 0.0, z:0.0}; 1000];
-    edges: Vec<Edge>,// = [Edge{a: 0, b: 0}; 1000];
+    pub params: Params,
+    pub pts: Vec<Node>,// = [Pnt3{x: 0.0, y: 0.0, z:0.0}; 1000];
+    pub edges: Vec<Edge>,// = [Edge{a: 0, b: 0}; 1000];
     pub tris: Vec<Pnt3<u32>>,
+    pub collisions: usize,
+    pub stopped: Option<String>,
+    pub schedule: Vec<Track>,
+    pub fields: Fields,
 }
 
 fn hsl(h: f32, s: f32, l: f32) -> Pnt3<f32> {
@@ -87,16 +98,29 @@ impl State {
 
     pub fn init() -> State {
         State{
+            format: FORMAT,
+            precision: real::PRECISION,
             time: 0,
+            params: Params::default(),
             pts: vec![],
             edges: vec![],
             // added later
             tris: vec![],
+            collisions: 0,
+            stopped: None,
+            schedule: vec![],
+            fields: Fields::none(),
         }
     }
 
     pub fn print_info(&self) {
         println!("Edges: {}, Points: {}, time: {}", self.edges.len(), self.pts.len(), self.time);
+        if self.params.collide {
+            println!("Collisions resolved: {}", self.collisions);
+        }
+        if let Some(ref reason) = self.stopped {
+            println!("Stopped: {}", reason);
+        }
     }
 
     #[inline]
@@ -105,10 +129,16 @@ impl State {
     }
 
     #[inline]
-    pub fn pos(&self, i: usize) -> &Pnt3<f32> {
+    pub fn pos(&self, i: usize) -> &Pnt3<Real> {
         &self.pts[i].pos
     }
 
+    /// Position of node `i` for drawing, always in f32.
+    #[inline]
+    pub fn render_pos(&self, i: usize) -> Pnt3<f32> {
+        real::to_render(&self.pts[i].pos)
+    }
+
     #[inline]
     pub fn edge_pts(&self, e: usize) -> (usize, usize) {
         (self.edges[e].a, self.edges[e].b)
@@ -119,10 +149,54 @@ impl State {
         self.edges.len()
     }
 
-    pub fn coords(&self) -> Vec<Pnt3<f32>> {
+    /// Middle of the anchor nodes the growth started from.
+    pub fn seed(&self) -> Pnt3<Real> {
+        let num = self.pts.len().min(10);
+        if num == 0 {
+            return na::orig();
+        }
+        let sum = self.pts[..num].iter().fold(Vec3::new(0.0, 0.0, 0.0), |sum, n| sum + n.pos.to_vec());
+        (sum / num as Real).to_pnt()
+    }
+
+    #[inline]
+    fn modulate(field: &Option<Field>, mid: &Pnt3<Real>, seed: &Pnt3<Real>) -> Real {
+        match *field {
+            Some(ref field) => field.eval(mid, seed),
+            None => 1.0,
+        }
+    }
+
+    #[inline]
+    fn midpoint(pos: &[Pnt3<Real>], a: usize, b: usize) -> Pnt3<Real> {
+        pos[a] + (pos[b] - pos[a]) / 2.0
+    }
+
+    pub fn kinetic(&self) -> Real {
+        self.pts.iter().fold(0.0, |sum, n| sum + n.vel.sqnorm() / 2.0)
+    }
+
+    /// Min and max corners of the box around every node.
+    pub fn bounds(&self) -> (Pnt3<Real>, Pnt3<Real>) {
+        let mut min = Pnt3::new(real::INFINITY, real::INFINITY, real::INFINITY);
+        let mut max = Pnt3::new(real::NEG_INFINITY, real::NEG_INFINITY, real::NEG_INFINITY);
+        for node in self.pts.iter() {
+            let Pnt3{x, y, z} = node.pos;
+            min = Pnt3::new(min.x.min(x), min.y.min(y), min.z.min(z));
+            max = Pnt3::new(max.x.max(x), max.y.max(y), max.z.max(z));
+        }
+        (min, max)
+    }
+
+    pub fn coords(&self) -> Vec<Pnt3<Real>> {
         self.pts.iter().map(|n| n.pos).collect()
     }
 
+    /// `coords` converted for the GPU.
+    pub fn render_coords(&self) -> Vec<Pnt3<f32>> {
+        self.pts.iter().map(|n| real::to_render(&n.pos)).collect()
+    }
+
     pub fn coord_colors(&self, off: f32) -> Vec<Pnt2<f32>> {
         self.pts.iter().map(|n| 
             Pnt2::new(
@@ -135,16 +209,17 @@ impl State {
     }
 
     pub fn start(&mut self, num: usize) {
-        let fnum = num as f32;
-        let scale = 2.0 * f32::consts::PI / fnum;
-        let circumference = fnum * MAX_LEN * 0.2;
-        let rad = circumference / 2.0 / f32::consts::PI;
+        let fnum = num as Real;
+        let scale = 2.0 * real::consts::PI / fnum;
+        let circumference = fnum * self.params.max_len * 0.2;
+        let rad = circumference / 2.0 / real::consts::PI;
         for i in 0..num {
-            let mrad = rad; // + (i as f32 / 20.0).sin();
+            let mrad = rad; // + (i as Real / 20.0).sin();
+            let dir = self.start_dir(i as Real * scale);
             self.pts.push(Node {
                 pos: Pnt3{
-                    x: (i as f32 * scale).cos() * mrad,
-                    y: (i as f32 * scale).sin() * mrad,
+                    x: (i as Real * scale).cos() * mrad,
+                    y: (i as Real * scale).sin() * mrad,
                     z: 0.0, // mrad,// - rad, // 0.0,
                 },
                 siblings: 2,
@@ -155,6 +230,11 @@ impl State {
                 dead: 0,
                 left: if i == 0 {num - 1} else {i - 1},
                 right: (i+1) % num,
+                chem: vec![],
+                exposure: 1.0,
+                dir: dir,
+                region: self.params.seed_region,
+                pinned: false,
             });
         }
 
@@ -162,15 +242,16 @@ impl State {
             self.edges.push(Edge{
                 a: i,
                 b: (i + 1) % num,
-                len: MAX_LEN / 4.0,
+                len: self.params.max_len / 4.0,
                 curlen: self.pts[i].pos.dist(&self.pts[((i + 1) % num)].pos),
                 age: 0,
+                region: self.params.seed_region,
             });
             /*
             self.edges.push(Edge{
                 a: i,
                 b: (i + 2) % num,
-                len: MAX_LEN / 2.0,
+                len: self.params.max_len / 2.0,
                 curlen: self.pts[i].pos.dist(&self.pts[((i + 2) % num)].pos),
                 age: 0,
             });
@@ -178,196 +259,228 @@ impl State {
         }
     }
 
-    pub fn tick(&mut self) {
+    /// Returns the number of edges split.
+    pub fn tick(&mut self) -> usize {
+        self.advance()
+    }
+
+    /// Tick, then measure how the run is doing.
+    pub fn tick_stats(&mut self) -> TickStats {
+        let splits = self.advance();
+        self.stats(splits)
+    }
+
+    // returns the number of edges split. Forces and crowding come from where
+    // the nodes start the tick, growth and splits go by those, and only then
+    // does everything move, new nodes included.
+    fn advance(&mut self) -> usize {
         self.time += 1;
-        self.adjust();
-        self.push_away();
+        if schedule::changes(&self.schedule, self.time) {
+            self.params = schedule::apply(&self.schedule, &self.params, self.time);
+        }
+        if !self.params.region_rules.is_empty() {
+            self.apply_region_rules();
+        }
+        let integrator = self.params.integrator.integrator();
+        let dt = self.params.dt;
+        self.measure();
+        let nclose = integrator.prepare(self, dt);
+        for (node, close) in self.pts.iter_mut().zip(nclose.into_iter()) {
+            node.nclose = close;
+        }
+        if self.params.chem_model != Model::Off {
+            self.react();
+        }
+        if self.params.aniso > 0.0 {
+            self.transport();
+        }
+        if self.params.env_model != Env::Off && self.time % self.params.env_every.max(1) == 0 {
+            self.expose();
+        }
         self.edge_grow();
-        self.edge_split();
-        self.move_things();
+        let splits = self.edge_split();
+        self.age_things();
+        integrator.step(self, dt);
+        if self.params.remesh_every > 0 && self.time % self.params.remesh_every == 0 {
+            self.remesh();
+        }
+        if self.params.collide {
+            self.collisions += self.collide();
+        }
+        splits
+    }
+
+    /// Accelerations on every node with the nodes at `pos`, plus the number
+    /// of nodes close to each one. Doesn't touch the state, so integrators
+    /// can evaluate it at intermediate positions.
+    pub fn forces(&self, pos: &[Pnt3<Real>]) -> Forces {
+        let mut accel: Vec<Vec3<Real>> = vec![na::zero(); pos.len()];
+        self.adjust(pos, &mut accel, 1.0);
+        let nclose = self.push_away(pos, &mut accel, 1.0);
+        self.gravitate(pos, &mut accel, 1.0);
+        Forces{accel: accel, nclose: nclose}
     }
 
-    fn adjust(&mut self) {
+    /// Add the spring and repulsion forces at `pos` over `dt` straight onto
+    /// `vel`, edge by edge and pair by pair as the original tick did, and
+    /// return the crowding counts.
+    pub fn kick(&self, pos: &[Pnt3<Real>], vel: &mut [Vec3<Real>], dt: Real) -> Vec<usize> {
+        self.adjust(pos, vel, dt);
+        self.push_away(pos, vel, dt)
+    }
+
+    /// How many nodes are close to each one with the nodes at `pos`.
+    pub fn crowding(&self, pos: &[Pnt3<Real>]) -> Vec<usize> {
+        let mut scratch: Vec<Vec3<Real>> = vec![na::zero(); pos.len()];
+        self.push_away(pos, &mut scratch, 1.0)
+    }
+
+    /// Zero out any velocity the anchor nodes aren't allowed to have.
+    pub fn constrain(&self, vel: &mut [Vec3<Real>]) {
+        for i in 0..vel.len().min(10) {
+            vel[i].y = 0.0;
+        }
+        for i in 0..vel.len() {
+            if self.pts[i].pinned {
+                vel[i] = na::zero();
+            }
+        }
+    }
+
+    fn measure(&mut self) {
         for i in 0..self.edges.len() {
-            let Edge{a, b, len, ..} = self.edges[i];
+            let Edge{a, b, ..} = self.edges[i];
+            self.edges[i].curlen = self.pts[a].pos.dist(&self.pts[b].pos);
+        }
+    }
+
+    fn adjust(&self, pos: &[Pnt3<Real>], accel: &mut [Vec3<Real>], dt: Real) {
+        let seed = self.seed();
+        for edge in self.edges.iter() {
+            let Edge{a, b, len, ..} = *edge;
             /* Worse perf!
-            if self.pts[a].dead > TOO_DEAD && self.pts[b].dead > TOO_DEAD {
+            if self.pts[a].dead > self.params.too_dead && self.pts[b].dead > self.params.too_dead {
                 continue;
             }
             */
-            let p1 = self.pts[a].pos;
-            let p2 = self.pts[b].pos;
+            let p1 = pos[a];
+            let p2 = pos[b];
             let mag = p1.dist(&p2);
             /* Worse perf!
-            if (len - mag).abs() < TOLERANCE {
+            if (len - mag).abs() < self.params.tolerance {
                 continue;
             }
             */
-            self.edges[i].curlen = mag;
             let diff = (p2 - p1).normalize();
-            let mdiff = diff * (len - mag) / 2.0 * -STICK_K;
-            self.pts[a].vel = self.pts[a].vel + mdiff;
-            self.pts[b].vel = self.pts[b].vel - mdiff;
+            let mdiff = diff * (len - mag) / 2.0 * -self.stiffness(pos, &seed, edge);
+            accel[a] = accel[a] + mdiff * dt;
+            accel[b] = accel[b] - mdiff * dt;
         }
     }
 
+    /// The spring constant of `edge` with the nodes at `pos`: `stick_k`,
+    /// scaled by its region and the stiffness field.
+    pub fn stiffness(&self, pos: &[Pnt3<Real>], seed: &Pnt3<Real>, edge: &Edge) -> Real {
+        self.params.stick_k * self.region(edge.region).stiffness * State::modulate(&self.fields.stiffness, &State::midpoint(pos, edge.a, edge.b), seed)
+    }
+
+    /// How hard nodes `i` and `j` push apart: `avoid_k`, scaled by the mean
+    /// of their regions' repulsion.
+    pub fn repulsion(&self, i: usize, j: usize) -> Real {
+        self.params.avoid_k * (self.region(self.pts[i].region).repulsion + self.region(self.pts[j].region).repulsion) / 2.0
+    }
+
+    // max edge length, with the max_len field applied
+    fn max_len(&self, pos: &[Pnt3<Real>], seed: &Pnt3<Real>, a: usize, b: usize) -> Real {
+        self.params.max_len * State::modulate(&self.fields.max_len, &State::midpoint(pos, a, b), seed)
+    }
+
     fn edge_grow(&mut self) {
+        let pos = self.coords();
+        let seed = self.seed();
         for i in 0..self.edges.len() {
             self.edges[i].age += 1;
             let Edge{a, b, len, ..} = self.edges[i];
-            if len > MAX_LEN {
+            if len > self.max_len(&pos, &seed, a, b) {
                 continue;
             }
-            let max_crowd = if self.pts[a].pos.y.max(self.pts[b].pos.y) > GRAV_TOP {TOO_CROWDED + 20} else {TOO_CROWDED};
+            let mut rate = self.region(self.edges[i].region).growth * State::modulate(&self.fields.growth, &State::midpoint(&pos, a, b), &seed);
+            if self.params.chem_model != Model::Off {
+                let level = (self.morphogen(a) + self.morphogen(b)) / 2.0;
+                rate *= (1.0 + self.params.chem_growth * level).max(0.0);
+            }
+            if self.params.aniso > 0.0 {
+                rate *= 1.0 - self.params.aniso + self.params.aniso * self.alignment(a, b);
+            }
+            if self.params.env_model != Env::Off {
+                let exposure = (self.pts[a].exposure + self.pts[b].exposure) / 2.0;
+                rate *= self.params.env_min + (1.0 - self.params.env_min) * exposure;
+            }
+            let max_crowd = if self.pts[a].pos.y.max(self.pts[b].pos.y) > self.params.grav_top {self.params.too_crowded + 20} else {self.params.too_crowded};
             if self.pts[a].nclose > max_crowd && self.pts[b].nclose > max_crowd {
                 continue;
             }
-            let least = (self.pts[a].nclose as f32).min(self.pts[b].nclose as f32);
-            if least <= MIN_CROWD as f32 {
-                self.edges[i].len += MAX_SPEED;
+            let least = (self.pts[a].nclose as Real).min(self.pts[b].nclose as Real);
+            if least <= self.params.min_crowd as Real {
+                self.edges[i].len += self.params.max_speed * rate;
             } else {
-                self.edges[i].len += GROW_SPEED + (MAX_SPEED - GROW_SPEED) * (least - MIN_CROWD as f32) / (max_crowd as f32 - MIN_CROWD as f32);
+                self.edges[i].len += (self.params.grow_speed + (self.params.max_speed - self.params.grow_speed) * (least - self.params.min_crowd as Real) / (max_crowd as Real - self.params.min_crowd as Real)) * rate;
             }
         }
     }
 
-    fn push_away(&mut self) {
-        let mut bins: HashMap<(usize, usize, usize), Vec<usize>> = HashMap::new();
-        let mut minx = 0.0;
-        let mut miny = 0.0;
-        let mut minz = 0.0;
-        // TODO figure out: would using the max to reduce false-reads at the top-end of the
-        // help at all?
-        //let mut maxx = 0.0;
-        //let mut maxy = 0.0;
-        //let mut maxz = 0.0;
-        for i in 0..self.pts.len() {
-            let Pnt3{x, y, z} = self.pts[i].pos;
-            if x < minx {minx = x;}
-            //if x > maxx {maxx = x;}
-            if y < miny {miny = y;}
-            //if y > maxy {maxy = y;}
-            if z < minz {minz = z;}
-            //if z > maxz {maxz = z;}
-        }
-        //let xscale = (maxx - minx) / CLOSE_DIST;
-        //let yscale = (maxy - miny) / CLOSE_DIST;
-        //let zscale = (maxy - minz) / CLOSE_DIST;
-        for i in 0..self.pts.len() {
-            let Pnt3{x, y, z} = self.pts[i].pos;
-            let pos = (
-                ((x - minx) / CLOSE_DIST / 2.0).floor() as usize,
-                ((y - miny) / CLOSE_DIST / 2.0).floor() as usize,
-                ((z - minz) / CLOSE_DIST / 2.0).floor() as usize,
-            );
-            let val = bins.entry(pos).or_insert(vec![]);
-            val.push(i);
-        }
-        //println!("Min {} {} {}", minx, miny, minz);
-        //println!("Bin: {:?}", bins);
-        for i in 0..self.pts.len() {
+    fn push_away(&self, pos: &[Pnt3<Real>], accel: &mut [Vec3<Real>], dt: Real) -> Vec<usize> {
+        let grid = Grid::new(pos, self.params.close_dist * 2.0);
+        let mut nclose = vec![0; pos.len()];
+        for i in 0..pos.len() {
             let mut close: usize = 0;
-            let Pnt3{x, y, z} = self.pts[i].pos;
-            let xp = (x - minx) / CLOSE_DIST / 2.0;
-            let yp = (y - miny) / CLOSE_DIST / 2.0;
-            let zp = (z - minz) / CLOSE_DIST / 2.0;
-            let xn = xp as usize;
-            let yn = yp as usize;
-            let zn = zp as usize;
-            let nx = if xp.round() > xp {xn + 1} else if xn > 0 {xn - 1} else {xn};
-            let ny = if yp.round() > yp {yn + 1} else if yn > 0 {yn - 1} else {yn};
-            let nz = if zp.round() > zp {zn + 1} else if zn > 0 {zn - 1} else {zn};
-
-            match bins.get(&(xn, yn, zn)) {
-                Some(arr) => {
-                    for j in arr {close += self.push_two(i, *j);}
-                },
-                None => {}
-            }
-            if nx != xn {
-                match bins.get(&(nx, yn, zn)) {
-                Some(arr) => {
-                    for j in arr {close += self.push_two(i, *j);}
-                },
-                    None => {}
-                }
-                if ny != yn {
-                    match bins.get(&(nx, ny, zn)) {
-                Some(arr) => {
-                    for j in arr {close += self.push_two(i, *j);}
-                },
-                        None => {}
-                    }
-                    if nz != zn {
-                        match bins.get(&(nx, ny, nz)) {
-                Some(arr) => { for j in arr {close += self.push_two(i, *j);} },
-                            None => {}
-                        }
-                    }
-                }
-                if nz != zn {
-                    match bins.get(&(nx, yn, nz)) {
-                Some(arr) => { for j in arr {close += self.push_two(i, *j);} },
-                        None => {}
-                    }
-                }
-            }
-            if ny != yn {
-                match bins.get(&(xn, ny, zn)) {
-                Some(arr) => { for j in arr {close += self.push_two(i, *j);} },
-                    None => {}
-                }
-                if nz != zn {
-                    match bins.get(&(xn, ny, nz)) {
-                Some(arr) => { for j in arr {close += self.push_two(i, *j);} },
-                        None => {}
-                    }
-                }
-            }
-            if nz != zn {
-                match bins.get(&(xn, yn, nz)) {
-                Some(arr) => { for j in arr {close += self.push_two(i, *j);} },
-                    None => {}
-                }
+            for arr in grid.near(&pos[i]) {
+                for j in arr {close += self.push_two(pos, accel, dt, i, *j);}
             }
             /*
-            for j in 0..self.pts.len() {
-                close += self.push_two(i, j);
+            for j in 0..pos.len() {
+                close += self.push_two(pos, accel, dt, i, j);
             }
             */
-            self.pts[i].nclose = close;
+            nclose[i] = close;
         }
+        nclose
     }
 
-    fn push_two(&mut self, i: usize, j: usize) -> usize {
+    fn push_two(&self, pos: &[Pnt3<Real>], accel: &mut [Vec3<Real>], dt: Real, i: usize, j: usize) -> usize {
         if j == i || self.pts[i].left == j || self.pts[i].right == j {
             return 0;
         }
-        let atob = self.pts[j].pos - self.pts[i].pos;
+        let atob = pos[j] - pos[i];
         let dist = atob.norm();
-        if dist > PUSH_DIST {
-            return if dist < CLOSE_DIST {1} else {0}
+        if dist > self.params.push_dist {
+            return if dist < self.params.close_dist {1} else {0}
         }
-        if self.pts[i].dead > TOO_DEAD && self.pts[j].dead > TOO_DEAD {
+        if self.pts[i].dead > self.params.too_dead && self.pts[j].dead > self.params.too_dead {
             return 1;
         }
         let diff = atob.normalize();
-        let magdiff = diff * (PUSH_DIST - dist); // / 2.0;
-        if self.pts[i].dead > TOO_DEAD {
-            self.pts[j].vel = self.pts[j].vel - magdiff * -AVOID_K ;
-        } else if self.pts[j].dead > TOO_DEAD {
-            self.pts[i].vel = self.pts[i].vel + magdiff * -AVOID_K ;
+        let magdiff = diff * (self.params.push_dist - dist) * dt; // / 2.0;
+        let avoid_k = self.repulsion(i, j);
+        if self.pts[i].dead > self.params.too_dead {
+            accel[j] = accel[j] - magdiff * -avoid_k ;
+        } else if self.pts[j].dead > self.params.too_dead {
+            accel[i] = accel[i] + magdiff * -avoid_k ;
         } else {
-            self.pts[i].vel = self.pts[i].vel + magdiff * -AVOID_K / 2.0;
-            self.pts[j].vel = self.pts[j].vel - magdiff * -AVOID_K / 2.0;
+            accel[i] = accel[i] + magdiff * -avoid_k / 2.0;
+            accel[j] = accel[j] - magdiff * -avoid_k / 2.0;
         }
         return 1;
     }
 
-    fn edge_split(&mut self) {
+    fn edge_split(&mut self) -> usize {
         let len = self.edges.len();
+        let mut splits = 0;
+        let pos = self.coords();
+        let seed = self.seed();
         for i in 0..len {
-            if self.edges[i].len < MAX_LEN || self.edges[i].curlen < MAX_LEN {
+            let max_len = self.max_len(&pos, &seed, self.edges[i].a, self.edges[i].b);
+            if self.edges[i].len < max_len || self.edges[i].curlen < max_len {
                 continue;
             }
             let Edge{a, b, len, ..} = self.edges[i];
@@ -375,6 +488,10 @@ impl State {
             let npos = self.pts[a].pos + (self.pts[b].pos - self.pts[a].pos) / 2.0;
             let ob = self.edges[i].b;
             let trunk = self.pts[a].trunk || self.pts[b].trunk;
+            let chem = self.pts[a].chem.iter().zip(self.pts[b].chem.iter()).map(|(x, y)| (x + y) / 2.0).collect();
+            let exposure = (self.pts[a].exposure + self.pts[b].exposure) / 2.0;
+            let dir = self.blend_dir(a, b);
+            let region = self.edges[i].region;
             self.edges[i].age = 0;
             self.pts.push(Node{
                 pos: npos,
@@ -386,6 +503,11 @@ impl State {
                 trunk: trunk,
                 left: self.edges[i].a,
                 right: ob,
+                chem: chem,
+                exposure: exposure,
+                dir: dir,
+                region: region,
+                pinned: false,
             });
             self.tris.push(Pnt3::new(npt as u32, a as u32, b as u32));
             self.pts[a].siblings += 1;
@@ -398,6 +520,7 @@ impl State {
                 age: 0,
                 a: npt,
                 b: ob,
+                region: region,
             });
             let oa = self.edges[i].a;
             self.edges.push(Edge{
@@ -406,45 +529,50 @@ impl State {
                 age: 0,
                 a: oa,
                 b: ob,
+                region: region,
             });
             self.edges[i].len = len / 2.0;
             self.edges[i].b = npt;
+            splits += 1;
+        }
+        splits
+    }
+
+    pub fn gravitate(&self, pos: &[Pnt3<Real>], accel: &mut [Vec3<Real>], dt: Real) {
+        for i in 0..pos.len() {
+            if i >= 10 {
+                if self.pts[i].trunk {
+                    accel[i].y += self.params.gravity * dt;// * (self.params.grav_top - pos[i].y) / self.params.grav_top;
+                    /*
+                    if pos[i].y < self.params.grav_bottom {
+                        accel[i].y += self.params.gravity;
+                    } else {
+                        accel[i].y += self.params.gravity * (pos[i].y - self.params.grav_bottom) / (self.params.grav_top - self.params.grav_bottom);
+                    }
+                    */
+                }
+            } else {
+                accel[i].y = 0.0;
+            }
         }
     }
 
-    fn move_things(&mut self) {
+    fn age_things(&mut self) {
         for i in 0..self.pts.len() {
             /*
-            if self.pts[i].dead > TOO_DEAD {
+            if self.pts[i].dead > self.params.too_dead {
                 continue;
             }
-            if self.pts[i].nclose > TOO_CROWDED && self.pts[i].vel.norm() < DEAD_MOTION {
+            if self.pts[i].nclose > self.params.too_crowded && self.pts[i].vel.norm() < self.params.dead_motion {
                 self.pts[i].dead += 1;
             } else {
                 self.pts[i].dead = 0;
             }
             */
-            if i >= 10 {
-                if self.pts[i].pos.y > GRAV_TOP {
-                    self.pts[i].trunk = false;
-                }
-                if self.pts[i].trunk {
-                    self.pts[i].vel.y += GRAVITY;// * (GRAV_TOP - self.pts[i].pos.y) / GRAV_TOP;
-                    /*
-                    if self.pts[i].pos.y < GRAV_BOTTOM {
-                        self.pts[i].vel.y += GRAVITY;
-                    } else {
-                        self.pts[i].vel.y += GRAVITY * (self.pts[i].pos.y - GRAV_BOTTOM) / (GRAV_TOP - GRAV_BOTTOM);
-                    }
-                    */
-                }
-            } else {
-                self.pts[i].vel.y = 0.0;
+            if i >= 10 && self.pts[i].pos.y > self.params.grav_top {
+                self.pts[i].trunk = false;
             }
-            self.pts[i].vel = self.pts[i].vel * DAMP;
-            self.pts[i].pos = self.pts[i].pos + self.pts[i].vel;
             self.pts[i].age += 1;
         }
     }
 }
-
@@ -0,0 +1,193 @@
+extern crate nalgebra as na;
+use na::{Pnt3, Norm, FloatPnt};
+use state::State;
+use grid::Grid;
+use rustc_serialize::json;
+use std::fs::File;
+use std::io::Write;
+use real::Real;
+
+/// A snapshot of how stable the run is, taken right after a tick.
+#[derive(RustcEncodable, Clone, Debug)]
+pub struct TickStats {
+    pub time: i32,
+    pub nodes: usize,
+    pub edges: usize,
+    pub splits: usize,
+    pub kinetic: Real,
+    pub spring: Real,
+    pub repulsion: Real,
+    pub mean_strain: Real,
+    pub max_strain: Real,
+    pub nans: usize,
+}
+
+impl TickStats {
+    pub fn csv_header() -> &'static str {
+        "time,nodes,edges,splits,kinetic,spring,repulsion,mean_strain,max_strain,nans"
+    }
+
+    pub fn csv(&self) -> String {
+        format!("{},{},{},{},{},{},{},{},{},{}",
+                self.time, self.nodes, self.edges, self.splits,
+                self.kinetic, self.spring, self.repulsion,
+                self.mean_strain, self.max_strain, self.nans)
+    }
+
+    pub fn total(&self) -> Real {
+        self.kinetic + self.spring + self.repulsion
+    }
+
+    /// Why this tick looks like the run is blowing up, if it does.
+    pub fn blowup(&self, last: Option<&TickStats>, spike: Real) -> Option<String> {
+        if self.nans > 0 || self.total().is_nan() {
+            return Some(format!("{} nodes with NaN positions", self.nans));
+        }
+        if let Some(last) = last {
+            // ignore spikes off a near-zero base, they're just startup
+            if last.total() > 1e-4 && self.total() > last.total() * spike {
+                return Some(format!("energy went from {} to {}", last.total(), self.total()));
+            }
+        }
+        None
+    }
+}
+
+impl State {
+    pub fn stats(&self, splits: usize) -> TickStats {
+        let pos = self.coords();
+        let kinetic = self.kinetic();
+        let nans = pos.iter().filter(|p| p.x.is_nan() || p.y.is_nan() || p.z.is_nan()).count();
+
+        // matches the force in `adjust`, which pulls each end by half
+        let seed = self.seed();
+        let mut spring = 0.0;
+        let mut strain_sum = 0.0;
+        let mut max_strain: Real = 0.0;
+        for edge in self.edges.iter() {
+            let mag = pos[edge.a].dist(&pos[edge.b]);
+            spring += self.stiffness(&pos, &seed, edge) / 4.0 * (mag - edge.len).powi(2);
+            let strain = if edge.len > 0.0 {mag / edge.len} else {1.0};
+            strain_sum += strain;
+            max_strain = max_strain.max(strain);
+        }
+
+        // matches `push_two`, counting each pair once
+        let mut repulsion = 0.0;
+        let grid = Grid::new(&pos, self.params.close_dist * 2.0);
+        for i in 0..pos.len() {
+            for arr in grid.near(&pos[i]) {
+                for &j in arr {
+                    if j <= i || self.pts[i].left == j || self.pts[i].right == j {
+                        continue;
+                    }
+                    let dist = pos[i].dist(&pos[j]);
+                    if dist < self.params.push_dist {
+                        repulsion += self.repulsion(i, j) / 2.0 * (self.params.push_dist - dist).powi(2);
+                    }
+                }
+            }
+        }
+
+        TickStats {
+            time: self.time,
+            nodes: self.pts.len(),
+            edges: self.edges.len(),
+            splits: splits,
+            kinetic: kinetic,
+            spring: spring,
+            repulsion: repulsion,
+            mean_strain: if self.edges.len() > 0 {strain_sum / self.edges.len() as Real} else {1.0},
+            max_strain: max_strain,
+            nans: nans,
+        }
+    }
+}
+
+/// How many times the last tick's energy counts as a blow-up.
+pub const ENERGY_SPIKE: Real = 10.0;
+
+/// Streams `TickStats` to a file, as CSV if the name ends in `.csv` and as
+/// JSON lines otherwise.
+pub struct StatsWriter {
+    out: File,
+    csv: bool,
+}
+
+impl StatsWriter {
+    pub fn new(path: String) -> StatsWriter {
+        let mut out = File::create(path.clone()).ok().expect(&format!("Can't write to {}", path));
+        let csv = path.ends_with(".csv");
+        if csv {
+            writeln!(out, "{}", TickStats::csv_header()).ok().expect("Writing stats");
+        }
+        StatsWriter{out: out, csv: csv}
+    }
+
+    pub fn write(&mut self, stats: &TickStats) {
+        let line = if self.csv {stats.csv()} else {json::encode(stats).ok().expect("Encoding stats")};
+        writeln!(self.out, "{}", line).ok().expect("Writing stats");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TickStats, ENERGY_SPIKE};
+    use params::Region;
+    use real::{self, Real};
+    use testing::{node, edge, anchored};
+
+    #[test]
+    fn measures_strain() {
+        let mut state = anchored(vec![node(0.0, 0.0, 0.0), node(2.0, 0.0, 0.0), node(2.0, 1.0, 0.0)]);
+        state.edges.push(edge(10, 11, 1.0));
+        state.edges.push(edge(11, 12, 1.0));
+        let stats = state.stats(3);
+        assert_eq!((stats.nodes, stats.edges, stats.splits), (13, 2, 3));
+        assert_eq!(stats.max_strain, 2.0);
+        assert_eq!(stats.mean_strain, 1.5);
+        assert_eq!(stats.nans, 0);
+        state.pts[12].pos.x = real::INFINITY - real::INFINITY;
+        assert_eq!(state.stats(0).nans, 1);
+    }
+
+    fn energy(kinetic: Real) -> TickStats {
+        TickStats{time: 0, nodes: 0, edges: 0, splits: 0, kinetic: kinetic, spring: 0.0, repulsion: 0.0, mean_strain: 1.0, max_strain: 1.0, nans: 0}
+    }
+
+    #[test]
+    fn flags_blowups() {
+        assert!(energy(1.0).blowup(Some(&energy(1.0)), ENERGY_SPIKE).is_none());
+        assert!(energy(20.0).blowup(Some(&energy(1.0)), ENERGY_SPIKE).is_some());
+        // a jump off nearly nothing is just the run starting
+        assert!(energy(1.0).blowup(Some(&energy(1e-5)), ENERGY_SPIKE).is_none());
+        assert!(TickStats{nans: 2, ..energy(1.0)}.blowup(None, ENERGY_SPIKE).is_some());
+    }
+
+    fn stiff() -> Region {
+        Region{stiffness: 2.0, repulsion: 3.0, ..Region::plain()}
+    }
+
+    #[test]
+    fn spring_energy_follows_region_stiffness() {
+        let mut state = anchored(vec![node(0.0, 0.0, 0.0), node(2.0, 0.0, 0.0)]);
+        state.edges.push(edge(10, 11, 1.0));
+        let plain = state.stats(0).spring;
+        assert!((plain - state.params.stick_k / 4.0).abs() < 1e-6);
+        state.params.regions = vec![Region::plain(), stiff()];
+        state.edges[0].region = 1;
+        assert!((state.stats(0).spring - plain * 2.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn repulsion_energy_follows_region_repulsion() {
+        let mut state = anchored(vec![node(0.0, 0.0, 0.0), node(0.4, 0.0, 0.0)]);
+        let plain = state.stats(0).repulsion;
+        assert!(plain > 0.0);
+        state.params.regions = vec![Region::plain(), stiff()];
+        state.pts[10].region = 1;
+        assert!((state.stats(0).repulsion - plain * 2.0).abs() < 1e-6);
+        state.pts[11].region = 1;
+        assert!((state.stats(0).repulsion - plain * 3.0).abs() < 1e-6);
+    }
+}
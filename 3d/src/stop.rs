@@ -0,0 +1,99 @@
+extern crate time;
+use params::Stop;
+use state::State;
+
+/// Tracks the `Stop` conditions across ticks.
+pub struct Stopper {
+    stop: Stop,
+    start: time::Timespec,
+    still: i32,
+    unsplit: i32,
+}
+
+impl Stopper {
+    pub fn new(stop: Stop) -> Stopper {
+        Stopper {
+            stop: stop,
+            start: time::get_time(),
+            still: 0,
+            unsplit: 0,
+        }
+    }
+
+    /// Call after every tick, with the number of splits it made. Returns why
+    /// the run should stop, if it should.
+    pub fn check(&mut self, state: &State, splits: usize) -> Option<String> {
+        if let Some(max) = self.stop.max_nodes {
+            if state.pts.len() >= max {
+                return Some(format!("node count {} reached {}", state.pts.len(), max));
+            }
+        }
+        if let Some(max) = self.stop.max_size {
+            let (min, maxp) = state.bounds();
+            let size = (maxp.x - min.x).max(maxp.y - min.y).max(maxp.z - min.z);
+            if size >= max {
+                return Some(format!("bounding box size {} reached {}", size, max));
+            }
+        }
+        if let Some(min) = self.stop.min_kinetic {
+            self.still = if state.kinetic() < min {self.still + 1} else {0};
+            if self.still >= self.stop.still_ticks {
+                return Some(format!("kinetic energy below {} for {} ticks", min, self.still));
+            }
+        }
+        if let Some(ticks) = self.stop.no_split_ticks {
+            self.unsplit = if splits == 0 {self.unsplit + 1} else {0};
+            if self.unsplit >= ticks {
+                return Some(format!("no splits for {} ticks", self.unsplit));
+            }
+        }
+        if let Some(budget) = self.stop.budget_secs {
+            let spent = (time::get_time() - self.start).num_seconds();
+            if spent >= budget {
+                return Some(format!("wall clock budget of {}s used up", budget));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Stopper;
+    use na::Vec3;
+    use params::Params;
+    use testing::{node, anchored};
+
+    #[test]
+    fn stops_once_still_for_long_enough() {
+        let mut stop = Params::default().stop;
+        stop.min_kinetic = Some(0.01);
+        stop.still_ticks = 3;
+        let mut stopper = Stopper::new(stop);
+        let mut state = anchored(vec![node(0.0, 0.0, 0.0)]);
+        state.pts[10].vel = Vec3::new(1.0, 0.0, 0.0);
+        assert_eq!(stopper.check(&state, 0), None);
+        state.pts[10].vel = Vec3::new(0.0, 0.0, 0.0);
+        assert_eq!(stopper.check(&state, 0), None);
+        assert_eq!(stopper.check(&state, 0), None);
+        // a single lively tick starts the count again
+        state.pts[10].vel = Vec3::new(1.0, 0.0, 0.0);
+        assert_eq!(stopper.check(&state, 0), None);
+        state.pts[10].vel = Vec3::new(0.0, 0.0, 0.0);
+        assert_eq!(stopper.check(&state, 0), None);
+        assert_eq!(stopper.check(&state, 0), None);
+        assert!(stopper.check(&state, 0).is_some());
+    }
+
+    #[test]
+    fn stops_when_splits_dry_up() {
+        let mut stop = Params::default().stop;
+        stop.no_split_ticks = Some(2);
+        let mut stopper = Stopper::new(stop);
+        let state = anchored(vec![]);
+        assert_eq!(stopper.check(&state, 0), None);
+        assert_eq!(stopper.check(&state, 4), None);
+        assert_eq!(stopper.check(&state, 0), None);
+        assert!(stopper.check(&state, 0).is_some());
+    }
+}
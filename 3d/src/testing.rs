@@ -0,0 +1,39 @@
+//! Small hand-built meshes for the tests.
+extern crate nalgebra as na;
+use na::{Pnt3, Vec3};
+use state::{State, Node, Edge};
+use real::Real;
+
+pub fn node(x: Real, y: Real, z: Real) -> Node {
+    Node {
+        pos: Pnt3::new(x, y, z),
+        vel: Vec3::new(0.0, 0.0, 0.0),
+        nclose: 0,
+        siblings: 2,
+        age: 0,
+        dead: 0,
+        left: 0,
+        right: 0,
+        trunk: false,
+        chem: vec![],
+        exposure: 1.0,
+        dir: Vec3::new(1.0, 0.0, 0.0),
+        region: 0,
+        pinned: false,
+    }
+}
+
+pub fn edge(a: usize, b: usize, len: Real) -> Edge {
+    Edge{a: a, b: b, age: 0, len: len, curlen: 0.0, region: 0}
+}
+
+/// Ten unconnected anchors well off to one side, so the nodes under test
+/// start at 10 and move freely.
+pub fn anchored(extra: Vec<Node>) -> State {
+    let mut state = State::init();
+    for i in 0..10 {
+        state.pts.push(node(100.0 + i as Real * 5.0, 100.0, 0.0));
+    }
+    state.pts.extend(extra.into_iter());
+    state
+}
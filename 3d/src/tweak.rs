@@ -0,0 +1,114 @@
+use state::State;
+use schedule::{self, Track, Ease};
+use real::Real;
+
+/// Params the viewer can adjust live, with how far one press moves each.
+pub static TWEAKS: [(&'static str, Real); 16] = [
+    ("stick_k", 0.01),
+    ("avoid_k", 0.005),
+    ("grow_speed", 0.002),
+    ("max_len", 0.05),
+    ("max_speed", 0.005),
+    ("damp", 0.05),
+    ("dt", 0.1),
+    ("gravity", 0.002),
+    ("close_dist", 0.1),
+    ("push_dist", 0.1),
+    ("too_crowded", 1.0),
+    ("collide_dist", 0.01),
+    ("aniso", 0.05),
+    ("aniso_twist", 0.01),
+    ("drag_k", 0.01),
+    ("impulse", 0.1),
+];
+
+impl State {
+    /// Set `param` from the next tick on. The change goes into the schedule,
+    /// so it's saved with the state and a run from the saved schedule
+    /// replays it at the same tick. A loaded schedule's later keys for the
+    /// param stay, so the tweak holds until the next of them.
+    pub fn tweak(&mut self, param: &str, value: Real) {
+        let at = self.time + 1;
+        let old = schedule::get(&self.params, param).expect(&format!("Can't tweak {}", param));
+        match self.schedule.iter().position(|track| track.param == param) {
+            Some(k) => {
+                let track = &mut self.schedule[k];
+                // what it would have been, before any tweaks at this tick
+                let before = match track.keys.iter().find(|key| key.0 == at) {
+                    Some(key) => key.1,
+                    None => track.value(at),
+                };
+                track.keys.retain(|key| key.0 != at);
+                let next = track.keys.iter().position(|key| key.0 > at).unwrap_or(track.keys.len());
+                track.keys.insert(next, (at, value));
+                track.keys.insert(next, (at, before));
+                if let Some(&(time, _)) = track.keys.get(next + 2) {
+                    println!("{} holds until the schedule's key at tick {}", param, time);
+                }
+            },
+            None => {
+                self.schedule.push(Track {
+                    param: param.to_string(),
+                    ease: Ease::Step,
+                    keys: vec![(at, old), (at, value)],
+                });
+            },
+        }
+        // just this one, so the panel shows it; the rest of the schedule
+        // moves on with the tick as usual
+        self.params = schedule::with(&self.params, param, value);
+        println!("Tick {}: {} {} -> {}", at, param, old, value);
+    }
+
+    /// Current values of everything in `TWEAKS`.
+    pub fn tweak_values(&self) -> Vec<Real> {
+        TWEAKS.iter().map(|&(param, _)| schedule::get(&self.params, param).unwrap_or(0.0)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use state::State;
+    use schedule::{self, Track, Ease};
+
+    #[test]
+    fn tweaks_within_a_tick_add_up() {
+        let mut state = State::init();
+        let start = schedule::get(&state.params, "max_len").unwrap();
+        state.tweak("max_len", start + 0.5);
+        let once = schedule::get(&state.params, "max_len").unwrap();
+        assert_eq!(once, start + 0.5);
+        state.tweak("max_len", once + 0.5);
+        assert_eq!(schedule::get(&state.params, "max_len").unwrap(), start + 0.5 + 0.5);
+        // before the tweak it's as it was, and from the next tick it's the last value
+        let track = &state.schedule[0];
+        assert_eq!(track.keys, vec![(1, start), (1, start + 0.5 + 0.5)]);
+        assert_eq!(track.value(0), start);
+        assert_eq!(track.value(1), start + 0.5 + 0.5);
+    }
+
+    #[test]
+    fn tweaks_keep_later_keys() {
+        let mut state = State::init();
+        state.time = 4;
+        state.schedule.push(Track{param: "max_len".to_string(), ease: Ease::Linear, keys: vec![(0, 1.0), (10, 2.0)]});
+        state.schedule.push(Track{param: "stick_k".to_string(), ease: Ease::Step, keys: vec![(0, 0.5), (5, 0.25)]});
+        let stick_k = state.params.stick_k;
+        state.tweak("max_len", 5.0);
+        assert_eq!(state.schedule[0].keys, vec![(0, 1.0), (5, 1.5), (5, 5.0), (10, 2.0)]);
+        assert_eq!(state.schedule[0].value(10), 2.0);
+        // nothing else moves before the tick does
+        assert_eq!(state.params.max_len, 5.0);
+        assert_eq!(state.params.stick_k, stick_k);
+    }
+
+    #[test]
+    fn tweaks_hold_on_the_next_tick() {
+        let mut state = State::init();
+        let start = schedule::get(&state.params, "max_len").unwrap();
+        state.tweak("max_len", start * 2.0);
+        state.time += 1;
+        let params = schedule::apply(&state.schedule, &state.params, state.time);
+        assert_eq!(params.max_len, start * 2.0);
+    }
+}
@@ -1,12 +1,97 @@
 extern crate bincode;
-use state::{State};
+extern crate nalgebra as na;
+use na::{Pnt3, Vec3};
+use state::{self, State, Node, Edge};
 use std::fs::File;
+use std::io::{Seek, SeekFrom};
 use bincode::SizeLimit;
+use real::{self, Real};
 
-pub fn load_maybe(infile: Option<String>, num: usize) -> State {
+// the layout saves had before they carried a format version: always f32,
+// and no params, since those were still constants
+#[derive(RustcEncodable, RustcDecodable)]
+struct OldEdge {
+    a: usize,
+    b: usize,
+    age: usize,
+    len: f32,
+    curlen: f32,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct OldNode {
+    pos: Pnt3<f32>,
+    vel: Vec3<f32>,
+    nclose: usize,
+    siblings: usize,
+    age: usize,
+    dead: i32,
+    left: usize,
+    right: usize,
+    trunk: bool,
+}
+
+#[derive(RustcEncodable, RustcDecodable)]
+struct OldState {
+    time: i32,
+    pts: Vec<OldNode>,
+    edges: Vec<OldEdge>,
+    tris: Vec<Pnt3<u32>>,
+}
+
+// everything the old layout didn't have starts as a fresh state would;
+// directions follow the ring round the z axis, like the seed's
+fn upgrade(old: OldState) -> State {
+    let mut state = State::init();
+    state.time = old.time;
+    for n in old.pts {
+        let pos = Pnt3::new(n.pos.x as Real, n.pos.y as Real, n.pos.z as Real);
+        let dir = state.start_dir(pos.y.atan2(pos.x));
+        state.pts.push(Node {
+            pos: pos,
+            vel: Vec3::new(n.vel.x as Real, n.vel.y as Real, n.vel.z as Real),
+            nclose: n.nclose,
+            siblings: n.siblings,
+            age: n.age,
+            dead: n.dead,
+            left: n.left,
+            right: n.right,
+            trunk: n.trunk,
+            chem: vec![],
+            exposure: 1.0,
+            dir: dir,
+            region: 0,
+            pinned: false,
+        });
+    }
+    state.edges = old.edges.into_iter().map(|e| Edge {
+        a: e.a,
+        b: e.b,
+        age: e.age,
+        len: e.len as Real,
+        curlen: e.curlen as Real,
+        region: 0,
+    }).collect();
+    state.tris = old.tris;
+    state
+}
+
+/// Load `infile`, or start a fresh ring of `num` nodes, with `setup`
+/// applied either way. A fresh state is set up before it's seeded, so
+/// params like `seed_region` and `aniso_dir` reach the seed nodes.
+pub fn load_maybe(infile: Option<String>, num: usize, setup: &Fn(&mut State)) -> State {
     match infile {
-        Some(fname) => load_state(fname),
-        _ => new_state(num),
+        Some(fname) => {
+            let mut state = load_state(fname);
+            setup(&mut state);
+            state
+        },
+        _ => {
+            let mut state = State::init();
+            setup(&mut state);
+            state.start(num);
+            state
+        },
     }
 }
 
@@ -17,12 +102,64 @@ pub fn write_out(state: &State, outfile: String) {
 
 pub fn load_state(fname: String) -> State {
     let mut file = File::open(fname.clone()).ok().expect(&format!("Can't read from file: {}", fname));
+    // `format` and `precision` come first, so peek at them before decoding
+    // the rest
+    let (format, precision): (u32, u8) = bincode::decode_from(&mut file, SizeLimit::Infinite).ok().expect(&format!("Unable to load state - is the format right? {}", fname));
+    if format != state::FORMAT {
+        if format >> 16 == state::FORMAT >> 16 {
+            panic!("{} was saved in format version {}, but this build reads version {}; load it with the grow that wrote it", fname, format & 0xffff, state::FORMAT & 0xffff);
+        }
+        // no format version, so it's from before there was one
+        file.seek(SeekFrom::Start(0)).ok().expect(&format!("Can't read from file: {}", fname));
+        let old: OldState = bincode::decode_from(&mut file, SizeLimit::Infinite).ok().expect(&format!("Unable to load state - is the format right? {}", fname));
+        println!("{} was saved by an older grow; loading it with default params", fname);
+        return upgrade(old);
+    }
+    if precision != real::PRECISION {
+        panic!("{} was saved with f{} precision, but this build uses f{} (see the f64 feature)", fname, precision, real::PRECISION);
+    }
+    file.seek(SeekFrom::Start(0)).ok().expect(&format!("Can't read from file: {}", fname));
     bincode::decode_from(&mut file, SizeLimit::Infinite).ok().expect(&format!("Unable to load state - is the format right? {}", fname))
 }
 
-pub fn new_state(num: usize) -> State {
-    let mut state = State::init();
-    state.start(num);
-    state
-}
 
+#[cfg(test)]
+mod tests {
+    extern crate bincode;
+    use super::{OldState, OldNode, OldEdge, load_state};
+    use na::{Pnt3, Vec3};
+    use bincode::SizeLimit;
+    use std::env;
+    use std::fs::{self, File};
+
+    fn old_node(x: f32, y: f32) -> OldNode {
+        OldNode{pos: Pnt3::new(x, y, 0.5), vel: Vec3::new(0.0, 0.0, 0.25), nclose: 3, siblings: 2, age: 7, dead: 0, left: 1, right: 1, trunk: true}
+    }
+
+    #[test]
+    fn loads_saves_from_before_the_format_version() {
+        let old = OldState{
+            time: 42,
+            pts: vec![old_node(1.0, 0.0), old_node(0.0, 1.0)],
+            edges: vec![OldEdge{a: 0, b: 1, age: 7, len: 0.5, curlen: 1.5}],
+            tris: vec![],
+        };
+        let path = env::temp_dir().join("grow-old-layout.bin");
+        {
+            let mut out = File::create(&path).unwrap();
+            bincode::encode_into(&old, &mut out, SizeLimit::Infinite).unwrap();
+        }
+        let state = load_state(path.to_str().unwrap().to_string());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(state.time, 42);
+        assert_eq!(state.pts.len(), 2);
+        assert_eq!(state.pts[1].pos, Pnt3::new(0.0, 1.0, 0.5));
+        assert_eq!(state.pts[1].vel, Vec3::new(0.0, 0.0, 0.25));
+        assert_eq!(state.pts[1].age, 7);
+        assert!(state.pts[1].trunk);
+        assert_eq!(state.edges[0].b, 1);
+        assert_eq!(state.edges[0].curlen, 1.5);
+        assert_eq!(state.pts[0].exposure, 1.0);
+        assert!(!state.pts[0].pinned);
+    }
+}